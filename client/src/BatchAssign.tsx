@@ -19,6 +19,7 @@ type Program = {
   program: string;
   cuttingTime: number;
   repeats: number;
+  dueDate: string | null;
 };
 
 const getMachines = async () => {
@@ -33,7 +34,12 @@ const getPrograms = async (machine: string) => {
   localStorage.setItem("machine", machine);
 
   const response = await fetch(`/api/${machine}`);
-  return response.json();
+  const { programs, truncated } = await response.json();
+  if (truncated) {
+    console.warn(`Programs for ${machine} were truncated`);
+  }
+
+  return programs;
 };
 
 export const BatchAssign: Component = () => {
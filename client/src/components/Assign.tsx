@@ -12,6 +12,7 @@ import {
 } from "solid-js";
 import { Portal } from "solid-js/web";
 import { Batch } from "../api/batch";
+import { getOperator } from "../utils";
 
 type Props = {
   name: string;
@@ -50,7 +51,10 @@ export const NestAssign: Component<Props> = (props: Props) => {
         batch: batch(),
         state: state(),
       }),
-      headers: { "Content-type": "application/json; charset=UTF-8" },
+      headers: {
+        "Content-type": "application/json; charset=UTF-8",
+        "X-Operator": getOperator(),
+      },
     });
 
     switch (state()) {
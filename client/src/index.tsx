@@ -9,6 +9,7 @@ import { BatchAssign } from "./BatchAssign";
 import { BatchListing } from "./BatchListing";
 import { NotFound } from "./NotFound";
 import { Feedback } from "./Feedback";
+import { getOperator, setOperator } from "./utils";
 
 const root = document.getElementById("root");
 
@@ -33,6 +34,12 @@ const Layout = (props: any) => {
             Sigmanest Feedback
           </A>
         </div>
+        <input
+          class="rounded-lg bg-gray-200 px-2 py-1"
+          placeholder="Operator"
+          value={getOperator()}
+          onChange={(e) => setOperator(e.currentTarget.value)}
+        />
       </nav>
       <main class="m-4 flex h-4/5 grow flex-col place-items-center justify-around">
         {props.children}
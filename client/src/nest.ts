@@ -25,6 +25,7 @@ export type Program = {
 export type Sheet = {
   materialMaster: string;
   sheetName: string;
+  isSingleton: boolean;
 };
 
 export type Remnant = {
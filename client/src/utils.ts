@@ -5,3 +5,9 @@ export const cuttingTimeStr = (runtime: number): string => {
 
   return `${hours}:${minutes}:${seconds}`;
 };
+
+// operator sent with write requests in the `X-Operator` header
+export const getOperator = (): string => localStorage.getItem("operator") ?? "";
+
+export const setOperator = (operator: string) =>
+  localStorage.setItem("operator", operator.trim());
@@ -0,0 +1,67 @@
+-- Purpose: Create tables owned by the sigmanest-interface server
+USE SNDBaseISap;
+
+-- State transitions posted to `POST /nest/:nest`
+CREATE TABLE dbo.ProgramStateLog (
+	Id INT IDENTITY(1,1) PRIMARY KEY,
+	ProgramName VARCHAR(50) NOT NULL,
+	Batch VARCHAR(50),
+
+	-- Initiated, Processing, Complete or Cancelled
+	State VARCHAR(16) NOT NULL,
+
+	-- from the `X-Operator` header of the request
+	Operator VARCHAR(50),
+
+	-- why the state was changed, such as for bulk cancellations
+	Reason VARCHAR(255),
+	LoggedAt DATETIME2 NOT NULL DEFAULT SYSDATETIME()
+);
+CREATE INDEX IX_ProgramStateLog_ProgramName ON dbo.ProgramStateLog (ProgramName, LoggedAt);
+GO
+
+-- SimTrans transactions posted by the server
+-- 	TransAct rows are removed once SimTrans processes them, so this keeps the history
+CREATE TABLE dbo.SimTransLog (
+	Id INT IDENTITY(1,1) PRIMARY KEY,
+	TransType VARCHAR(8) NOT NULL,
+	ProgramName VARCHAR(50) NOT NULL,
+	ProgramRepeat INT NOT NULL,
+	Operator VARCHAR(50),
+	PostedAt DATETIME2 NOT NULL DEFAULT SYSDATETIME()
+);
+CREATE INDEX IX_SimTransLog_PostedAt ON dbo.SimTransLog (PostedAt, TransType);
+GO
+
+-- QA review status of feedback entries, set via `POST /feedback/:id/resolve`
+CREATE TABLE dbo.FeedbackResolution (
+	ArchivePacketID INT PRIMARY KEY,
+
+	-- open, reviewed or resolved
+	Status VARCHAR(16) NOT NULL,
+	ResolvedAt DATETIME2 NOT NULL DEFAULT SYSDATETIME()
+);
+GO
+
+-- Priorities of programs, set via `POST /nest/:nest/priority`
+-- 	programs without a row have priority 0, and higher priorities are cut first
+CREATE TABLE dbo.ProgramPriority (
+	ProgramName VARCHAR(50) PRIMARY KEY,
+	Priority INT NOT NULL,
+
+	-- from the `X-Operator` header of the request
+	Operator VARCHAR(50),
+	SetAt DATETIME2 NOT NULL DEFAULT SYSDATETIME()
+);
+GO
+
+-- Programs hidden from their machine's queue, set via `POST /nest/:nest/hide`
+-- 	such as templates and test nests that cannot be removed from Sigmanest
+CREATE TABLE dbo.HiddenProgram (
+	ProgramName VARCHAR(50) PRIMARY KEY,
+
+	-- from the `X-Operator` header of the request
+	Operator VARCHAR(50),
+	HiddenAt DATETIME2 NOT NULL DEFAULT SYSDATETIME()
+);
+GO
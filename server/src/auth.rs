@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{Error, Result};
+
+/// Header clients send their API key in
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Header clients send the operator performing an update in
+pub const OPERATOR_HEADER: &str = "X-Operator";
+
+/// Longest operator name, matching `ProgramStateLog.Operator`
+const OPERATOR_MAX_LEN: usize = 50;
+
+/// API key required for write/admin endpoints, from env `SN_WRITE_API_KEY`
+///
+/// If no key is configured, all write protected requests are rejected.
+#[derive(Debug, Clone)]
+pub struct WriteKey(Option<Arc<str>>);
+
+impl WriteKey {
+    pub fn from_env() -> Self {
+        match std::env::var("SN_WRITE_API_KEY") {
+            Ok(key) if !key.is_empty() => Self(Some(key.into())),
+            _ => {
+                log::warn!("SN_WRITE_API_KEY is not set; write protected endpoints are disabled");
+                Self(None)
+            }
+        }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.0.as_deref().is_some_and(|expected| expected == key)
+    }
+}
+
+/// middleware rejecting requests without the write API key
+pub async fn require_write_key(
+    State(key): State<WriteKey>,
+    request: Request,
+    next: Next,
+) -> Result<Response> {
+    let provided = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    match provided {
+        Some(provided) if key.matches(provided) => Ok(next.run(request).await),
+        _ => {
+            log::warn!("Rejected unauthorized request to {}", request.uri());
+            Err(Error::Unauthorized)
+        }
+    }
+}
+
+/// Operator performing a write request, from the `X-Operator` header
+///
+/// Rejects requests without an operator so every logged change has an actor.
+#[derive(Debug, Clone)]
+pub struct Operator(pub String);
+
+impl Operator {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Operator {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let operator = parts
+            .headers
+            .get(OPERATOR_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| Error::BadRequest(format!("Invalid {} header", OPERATOR_HEADER)))
+            })
+            .transpose()?
+            .map(str::trim)
+            .unwrap_or_default();
+
+        match operator.len() {
+            0 => Err(Error::BadRequest(format!(
+                "{} header is required",
+                OPERATOR_HEADER
+            ))),
+            len if len > OPERATOR_MAX_LEN => Err(Error::BadRequest(format!(
+                "{} cannot be longer than {} characters",
+                OPERATOR_HEADER, OPERATOR_MAX_LEN
+            ))),
+            _ => Ok(Self(operator.into())),
+        }
+    }
+}
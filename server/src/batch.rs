@@ -1,5 +1,29 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::{db::api::Sheet, Error};
+
+/// File batches are read from
+const BATCH_SOURCE: &str = "batches.csv";
+
+/// Time to wait before reading the batch source again after it was unavailable
+pub const BATCH_SOURCE_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Header of `GET /batches` with the number of malformed batch records skipped
+pub const BATCHES_SKIPPED_HEADER: &str = "X-Batches-Skipped";
+
+/// Header of `GET /batches` with whether batches have been loaded, as the
+/// list is empty rather than failing while none are, if configured
+pub const BATCHES_LOADED_HEADER: &str = "X-Batches-Loaded";
+
+/// Media type of CSV batch lists
+pub const CSV_CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+
+/// Header row of CSV batch lists, matching the JSON field names
+const CSV_HEADER: [&str; 5] = ["id", "mm", "sheetName", "type", "qty"];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct Batch {
@@ -8,14 +32,300 @@ pub struct Batch {
     pub sheet_name: String,
     #[serde(rename(deserialize = "remnant"))]
     pub r#type: BatchType,
+    /// sheets remaining in the batch, from the optional `qty` column of the batch source
+    #[serde(default = "default_qty")]
+    pub qty: u32,
+}
+
+/// batches without a quantity are a single sheet
+pub fn default_qty() -> u32 {
+    1
 }
 
 impl Batch {
-    pub fn get_batches() -> crate::Result<Vec<Self>> {
-        csv::Reader::from_path("batches.csv")?
-            .into_deserialize::<Batch>()
-            .map(|r| r.map_err(crate::Error::from))
-            .collect()
+    /// read batches from the batch source, with the malformed records that were skipped
+    ///
+    /// Fails with [`Error::Unavailable`] if the source could not be read,
+    /// such as while it is locked or being replaced. A malformed record only
+    /// skips that record, so the rest of the batches are still served.
+    pub fn get_batches() -> crate::Result<(Vec<Self>, Vec<BatchError>)> {
+        let records = csv::Reader::from_path(BATCH_SOURCE)
+            .map_err(source_error)?
+            .into_deserialize::<Batch>();
+
+        let mut batches = Vec::new();
+        let mut errors = Vec::new();
+        for record in records {
+            match record {
+                Ok(batch) => batches.push(batch),
+                // the rest of the source cannot be read, rather than one record being bad
+                Err(e) if e.is_io_error() => return Err(source_error(e)),
+                Err(e) => {
+                    let error = BatchError::from(e);
+                    log::warn!("Skipped malformed batch record: {}", error.message);
+                    errors.push(error);
+                }
+            }
+        }
+
+        Ok((batches, errors))
+    }
+
+    /// time the batch source was last modified, if it can be read
+    pub fn source_modified() -> Option<DateTime<Utc>> {
+        std::fs::metadata(BATCH_SOURCE)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::from)
+    }
+
+    /// write batches as CSV, with a header row of the batch fields
+    ///
+    /// The header is written even if there are no batches, so the columns
+    /// are always there for spreadsheets.
+    pub fn to_csv(batches: &[Self]) -> crate::Result<String> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        writer.write_record(CSV_HEADER)?;
+        for batch in batches {
+            writer.serialize(batch)?;
+        }
+
+        let csv = writer.into_inner().map_err(|e| e.into_error())?;
+        Ok(String::from_utf8(csv).expect("batches are serialized as utf-8"))
+    }
+
+    /// take sheets consumed by a completed program from the batch, returning the sheets left
+    ///
+    /// The quantity stops at 0 if more sheets are consumed than remain.
+    pub fn consume(&mut self, sheets: u32) -> u32 {
+        if sheets > self.qty {
+            log::warn!(
+                "Batch {} has {} sheets, but {} were consumed",
+                self.id,
+                self.qty,
+                sheets
+            );
+        }
+        self.qty = self.qty.saturating_sub(sheets);
+
+        self.qty
+    }
+
+    /// batch is for a single named sheet rather than generic stock
+    pub fn is_singleton(&self) -> bool {
+        self.sheet_name != self.mm
+    }
+
+    /// batch can be used to cut a nest on the given sheet
+    ///
+    /// Nests on a singleton sheet need that sheet's batch, while nests on
+    /// generic stock can use any generic batch of the same material master.
+    pub fn matches_sheet(&self, sheet: &Sheet) -> bool {
+        match sheet.is_singleton {
+            true => self.sheet_name == sheet.sheet_name,
+            false => !self.is_singleton() && self.mm == sheet.material_master,
+        }
+    }
+
+    /// batch is generic stock of the same grade as a sheet, with a size
+    /// within `tolerance` of the sheet's
+    ///
+    /// Batches that exactly match the sheet are not included.
+    pub fn is_compatible(&self, sheet: &Sheet, tolerance: f64) -> bool {
+        if self.is_singleton() || self.matches_sheet(sheet) {
+            return false;
+        }
+
+        let (grade, size) = split_material(&self.mm);
+        let (sheet_grade, sheet_size) = split_material(&sheet.material_master);
+        match (size.parse::<f64>(), sheet_size.parse::<f64>()) {
+            (Ok(size), Ok(sheet_size)) => {
+                grade == sheet_grade && (size - sheet_size).abs() <= tolerance
+            }
+            _ => false,
+        }
+    }
+
+    /// reasons the batch cannot be used to cut a nest on the given sheet
+    ///
+    /// Uses the same rules as [`Self::matches_sheet`], and if `tolerance` is
+    /// given, generic batches within [`Self::is_compatible`] are also accepted.
+    /// The batch can be used if there are no reasons.
+    pub fn mismatches(&self, sheet: &Sheet, tolerance: Option<f64>) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.qty == 0 {
+            reasons.push(format!("Batch {} has no sheets left", self.id));
+        }
+
+        let accepted = self.matches_sheet(sheet)
+            || tolerance.is_some_and(|tolerance| self.is_compatible(sheet, tolerance));
+        if accepted {
+            return reasons;
+        }
+
+        match (sheet.is_singleton, self.is_singleton()) {
+            (true, _) => reasons.push(format!(
+                "Nest is on singleton sheet {}, but batch is for sheet {}",
+                sheet.sheet_name, self.sheet_name
+            )),
+            (false, true) => reasons.push(format!(
+                "Batch is for singleton sheet {}, but nest is on generic stock",
+                self.sheet_name
+            )),
+            (false, false) => {
+                let (grade, size) = split_material(&self.mm);
+                let (sheet_grade, sheet_size) = split_material(&sheet.material_master);
+                if grade != sheet_grade {
+                    reasons.push(format!(
+                        "Batch grade {} does not match nest grade {}",
+                        grade, sheet_grade
+                    ));
+                }
+                if size != sheet_size {
+                    reasons.push(match tolerance {
+                        Some(tolerance) => format!(
+                            "Batch size {} is not within {} of nest size {}",
+                            size, tolerance, sheet_size
+                        ),
+                        None => format!(
+                            "Batch size {} does not match nest size {}",
+                            size, sheet_size
+                        ),
+                    });
+                }
+            }
+        }
+
+        reasons
+    }
+}
+
+/// split a material master into its grade and size
+///
+/// Material masters are named `<grade>-<size>`, e.g. `50/50W-9006` is
+/// grade `50/50W` in size `9006`.
+pub fn split_material(mm: &str) -> (&str, &str) {
+    mm.rsplit_once('-').unwrap_or((mm, ""))
+}
+
+/// Material represented in the batches, with how many sheets are available
+///
+/// See [`split_material`] for how grade and size are named.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Material {
+    pub mm: String,
+    pub grade: String,
+    pub size: String,
+    /// sheets available in all batches of the material
+    pub qty: u32,
+    /// sheets available in batches of new stock
+    pub new: u32,
+    /// sheets available in batches of remnants
+    pub remnants: u32,
+}
+
+impl Material {
+    /// distinct materials of a list of batches, sorted by material master
+    pub fn from_batches(batches: &[Batch]) -> Vec<Self> {
+        let mut materials: BTreeMap<&str, Self> = BTreeMap::new();
+        for batch in batches {
+            let material = materials.entry(&batch.mm).or_insert_with(|| {
+                let (grade, size) = split_material(&batch.mm);
+                Self {
+                    mm: batch.mm.clone(),
+                    grade: grade.into(),
+                    size: size.into(),
+                    qty: 0,
+                    new: 0,
+                    remnants: 0,
+                }
+            });
+
+            material.qty += batch.qty;
+            match batch.r#type {
+                BatchType::New => material.new += batch.qty,
+                BatchType::Remnant => material.remnants += batch.qty,
+            }
+        }
+
+        materials.into_values().collect()
+    }
+}
+
+/// Record of the batch source that could not be read and was skipped
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchError {
+    /// line of the record in the batch source, if known
+    pub line: Option<u64>,
+    pub message: String,
+}
+
+impl From<csv::Error> for BatchError {
+    fn from(error: csv::Error) -> Self {
+        Self {
+            line: error.position().map(|pos| pos.line()),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Sheets of a material needed by queued programs, against the sheets in its batches
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialDemand {
+    pub mm: String,
+    pub required: u32,
+    pub available: u32,
+    /// sheets required beyond those available, 0 if there are enough
+    pub shortfall: u32,
+}
+
+impl MaterialDemand {
+    /// demand of a material, with the sheets available from its batches
+    pub fn new(mm: &str, required: u32, batches: &[Batch]) -> Self {
+        let available = batches
+            .iter()
+            .filter(|batch| batch.mm == mm)
+            .map(|batch| batch.qty)
+            .sum();
+
+        Self {
+            mm: mm.into(),
+            required,
+            available,
+            shortfall: required.saturating_sub(available),
+        }
+    }
+}
+
+/// check if an `Accept` header prefers CSV over JSON
+///
+/// JSON stays the default, so CSV is only used if it is listed before any JSON type.
+pub fn accepts_csv(accept: &str) -> bool {
+    for media_type in accept.split(',') {
+        let media_type = media_type.split(';').next().unwrap_or_default().trim();
+        match media_type {
+            "text/csv" => return true,
+            "application/json" | "*/*" => return false,
+            _ => (),
+        }
+    }
+
+    false
+}
+
+/// io errors reading the batch source are transient, others mean bad data
+fn source_error(error: csv::Error) -> Error {
+    match error.is_io_error() {
+        true => {
+            log::warn!("Batch source unavailable: {}", error);
+            Error::Unavailable(BATCH_SOURCE_COOLDOWN)
+        }
+        false => Error::from(error),
     }
 }
 
@@ -26,3 +336,64 @@ pub enum BatchType {
     #[serde(rename(deserialize = "Y"))]
     Remnant,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn batch(id: &str, mm: &str, r#type: BatchType, qty: u32) -> Batch {
+        Batch {
+            id: id.into(),
+            mm: mm.into(),
+            sheet_name: id.into(),
+            r#type,
+            qty,
+        }
+    }
+
+    #[test]
+    fn materials_add_up_available_sheets() {
+        let batches = [
+            batch("B1", "50/50W-0500", BatchType::New, 3),
+            batch("B2", "50/50W-0500", BatchType::New, 2),
+            batch("B3", "50/50W-0500", BatchType::Remnant, 1),
+            batch("B4", "A709-0750", BatchType::New, 4),
+        ];
+
+        let materials = Material::from_batches(&batches);
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].mm, "50/50W-0500");
+        assert_eq!(
+            (materials[0].qty, materials[0].new, materials[0].remnants),
+            (6, 5, 1)
+        );
+        assert_eq!(
+            (materials[1].qty, materials[1].new, materials[1].remnants),
+            (4, 4, 0)
+        );
+    }
+
+    #[test]
+    fn batch_serializes_as_camel_case() {
+        let batch = Batch {
+            id: "B1234".into(),
+            mm: "50/50W-0500".into(),
+            sheet_name: "S1234".into(),
+            r#type: BatchType::Remnant,
+            qty: 1,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&batch).unwrap(),
+            json!({
+                "id": "B1234",
+                "mm": "50/50W-0500",
+                "sheetName": "S1234",
+                "type": "Remnant",
+                "qty": 1,
+            })
+        );
+    }
+}
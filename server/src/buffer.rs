@@ -0,0 +1,277 @@
+//! Write-behind buffer of SimTrans completions
+//!
+//! Under heavy completion volume, inserting each completion into `TransAct`
+//! as it is made is slow. With `SN_SIMTRANS_BUFFER_SIZE` set, completions are
+//! collected here and posted together, see [`SimTransBuffer::flush`].
+//! Completions made while SimTrans is paused are held here too, until it is
+//! resumed.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex, Notify},
+};
+
+use crate::{
+    db::{
+        self,
+        api::{simtrans, PendingSimTrans},
+        Plants,
+    },
+    Error, Result,
+};
+
+/// File of completions that could not be posted, from env `SN_SIMTRANS_BUFFER_FILE`
+///
+/// Defaults to `simtrans_buffer.jsonl` in the working directory, so completions
+/// still buffered when the server stops are posted after a restart. Each line
+/// is a completion, so a new one is appended rather than the file rewritten.
+pub fn buffer_file() -> PathBuf {
+    std::env::var_os("SN_SIMTRANS_BUFFER_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("simtrans_buffer.jsonl"))
+}
+
+/// Outcome of a [`SimTransBuffer::flush`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Flushed {
+    pub posted: usize,
+    /// completions of programs that no longer exist, which cannot be posted
+    pub dropped: usize,
+    /// completions still buffered, including the ones that failed to post
+    pub pending: usize,
+}
+
+/// Completions waiting to be posted to SimTrans together
+#[derive(Debug, Default)]
+pub struct SimTransBuffer {
+    completions: Mutex<Vec<PendingSimTrans>>,
+    /// notified when the buffer reaches its size, to flush before the interval is up
+    full: Notify,
+    /// held while flushing, so a flush at shutdown waits for one in progress
+    flushing: Mutex<()>,
+}
+
+impl SimTransBuffer {
+    /// load the completions saved by the last run, if any
+    pub async fn load() -> Self {
+        let path = buffer_file();
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        // a line cut short by a crash while appending is the only one lost
+        let mut completions = Vec::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<PendingSimTrans>(line) {
+                Ok(completion) => completions.push(completion),
+                Err(e) => log::warn!(
+                    "Ignoring unreadable SimTrans completion in {:?}: {}",
+                    path,
+                    e
+                ),
+            }
+        }
+
+        log::info!("Loaded {} buffered SimTrans completions", completions.len());
+        Self {
+            completions: Mutex::new(completions),
+            ..Default::default()
+        }
+    }
+
+    /// save the completions not posted yet, removing the file if there are none
+    pub async fn save(&self) {
+        save(&self.completions.lock().await).await;
+    }
+
+    /// buffer a completion, waking the flush task once `size` are buffered
+    ///
+    /// Completions held while SimTrans is paused have no `size`, so they wait
+    /// for it to be resumed. The completion is appended to the [`buffer_file`]
+    /// before returning, so a completion accepted by the server is not lost if
+    /// it crashes before the next flush.
+    pub async fn push(&self, completion: PendingSimTrans, size: Option<usize>) {
+        let mut completions = self.completions.lock().await;
+        append(&completion).await;
+        completions.push(completion);
+
+        if size.is_some_and(|size| completions.len() >= size) {
+            self.full.notify_one();
+        }
+    }
+
+    /// number of completions waiting to be posted
+    pub async fn pending(&self) -> usize {
+        self.completions.lock().await.len()
+    }
+
+    /// wait until the buffer is full or `interval` is up, whichever is first
+    pub async fn wait(&self, interval: std::time::Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => (),
+            _ = self.full.notified() => (),
+        }
+    }
+
+    /// post the buffered completions, each plant's in as few statements as possible
+    ///
+    /// If `proc` is set, completions are posted one at a time through that
+    /// stored procedure instead, see [`simtrans::post_program_complete`].
+    /// Completions that fail to post are kept for the next flush, and the ones
+    /// left are saved to the [`buffer_file`].
+    pub async fn flush(&self, plants: &Plants, district: i32, proc: Option<&str>) -> Flushed {
+        let _flushing = self.flushing.lock().await;
+
+        // completions stay buffered, and saved, until they are known to be posted
+        let completions = self.completions.lock().await.clone();
+        if completions.is_empty() {
+            return Flushed::default();
+        }
+        let flushed = completions.len();
+
+        let mut by_plant: HashMap<String, Vec<PendingSimTrans>> = HashMap::new();
+        for completion in completions {
+            by_plant
+                .entry(completion.plant.clone())
+                .or_default()
+                .push(completion);
+        }
+
+        // stored procedures are called one completion at a time
+        let chunk_size = match proc {
+            Some(_) => 1,
+            None => simtrans::MAX_BATCHED_COMPLETIONS,
+        };
+
+        let mut outcome = Flushed::default();
+        let mut failed = Vec::new();
+        for (plant, completions) in by_plant {
+            for chunk in completions.chunks(chunk_size) {
+                match post(plants, &plant, chunk, district, proc).await {
+                    Ok(count) => {
+                        if count < chunk.len() {
+                            log::warn!(
+                                "Dropped {} buffered SimTrans completions of missing programs",
+                                chunk.len() - count
+                            );
+                        }
+                        outcome.posted += count;
+                        outcome.dropped += chunk.len() - count;
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "Failed to post {} buffered completions of plant {} to SimTrans",
+                            chunk.len(),
+                            plant
+                        );
+                        log::error!("{:#?}", e);
+                        failed.extend_from_slice(chunk);
+                    }
+                }
+            }
+        }
+
+        // completions are only added while flushing, so the flushed ones are
+        // still first, and failed ones go before any buffered in the meantime
+        let mut completions = self.completions.lock().await;
+        let added = completions.split_off(flushed);
+        *completions = failed;
+        completions.extend(added);
+        save(&completions).await;
+
+        outcome.pending = completions.len();
+        outcome
+    }
+}
+
+/// line of the [`buffer_file`] holding a completion
+fn line(completion: &PendingSimTrans) -> String {
+    let mut line = serde_json::to_string(completion).expect("completions are serializable");
+    line.push('\n');
+    line
+}
+
+/// append a completion to the [`buffer_file`]
+async fn append(completion: &PendingSimTrans) {
+    let appended = async {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(buffer_file())
+            .await?;
+        file.write_all(line(completion).as_bytes()).await?;
+        file.flush().await
+    };
+
+    if let Err(e) = appended.await {
+        log::error!("Failed to save buffered SimTrans completion");
+        log::error!("{:#?}", e);
+    }
+}
+
+/// save completions to the [`buffer_file`], removing it if there are none
+async fn save(completions: &[PendingSimTrans]) {
+    let saved = match completions.is_empty() {
+        true => match tokio::fs::remove_file(buffer_file()).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            removed => removed,
+        },
+        false => {
+            let contents: String = completions.iter().map(line).collect();
+            tokio::fs::write(buffer_file(), contents).await
+        }
+    };
+
+    if let Err(e) = saved {
+        log::error!("Failed to save SimTrans buffer");
+        log::error!("{:#?}", e);
+    }
+}
+
+/// post completions of one plant in a single statement, or one at a time
+/// through `proc`, returning the number posted
+async fn post(
+    plants: &Plants,
+    plant: &str,
+    completions: &[PendingSimTrans],
+    district: i32,
+    proc: Option<&str>,
+) -> Result<usize> {
+    let (_, pool) = plants.get(Some(plant))?;
+    let mut conn = pool.get_owned().await?;
+
+    let proc = match proc {
+        Some(proc) => proc,
+        None => {
+            return db::timed(
+                conn.breaker(),
+                simtrans::post_program_completes(&mut conn, completions, district),
+            )
+            .await
+        }
+    };
+
+    let mut posted = 0;
+    for completion in completions {
+        let breaker = conn.breaker();
+        let complete = simtrans::post_program_complete(
+            &mut conn,
+            &completion.program,
+            district,
+            &completion.operator,
+            &completion.trans_type,
+            Some(proc),
+        );
+        match db::timed(breaker, complete).await {
+            Ok(()) => posted += 1,
+            // retrying cannot post a program that no longer exists
+            Err(Error::NotFound(_)) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(posted)
+}
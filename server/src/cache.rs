@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::{default_qty, Batch, BatchType},
+    Result,
+};
+
+/// Oldest batch cache file that is loaded on startup, unless configured
+pub const CACHE_MAX_AGE: Duration = Duration::hours(12);
+
+/// Batch cache file, from env `SN_CACHE_FILE`
+///
+/// If unset, the batch cache is not persisted.
+pub fn cache_file() -> Option<PathBuf> {
+    std::env::var_os("SN_CACHE_FILE").map(PathBuf::from)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheFile {
+    saved_at: DateTime<Utc>,
+    batches: Vec<CachedBatch>,
+}
+
+/// `Batch` serializes and deserializes with different names,
+/// so the cache file uses its own representation
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedBatch {
+    id: String,
+    mm: String,
+    sheet_name: String,
+    remnant: bool,
+    #[serde(default = "default_qty")]
+    qty: u32,
+}
+
+impl From<&Batch> for CachedBatch {
+    fn from(batch: &Batch) -> Self {
+        Self {
+            id: batch.id.clone(),
+            mm: batch.mm.clone(),
+            sheet_name: batch.sheet_name.clone(),
+            remnant: matches!(batch.r#type, BatchType::Remnant),
+            qty: batch.qty,
+        }
+    }
+}
+
+impl From<CachedBatch> for Batch {
+    fn from(batch: CachedBatch) -> Self {
+        Self {
+            id: batch.id,
+            mm: batch.mm,
+            sheet_name: batch.sheet_name,
+            r#type: match batch.remnant {
+                true => BatchType::Remnant,
+                false => BatchType::New,
+            },
+            qty: batch.qty,
+        }
+    }
+}
+
+/// load batches from the cache file, if it exists and is not older than `max_age`
+///
+/// Returns the time the batches were saved with them.
+pub async fn load_batches(max_age: Duration) -> Option<(DateTime<Utc>, Vec<Batch>)> {
+    let path = cache_file()?;
+
+    let contents = match tokio::fs::read(&path).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            log::info!("No batch cache loaded from {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let cache: CacheFile = match serde_json::from_slice(&contents) {
+        Ok(cache) => cache,
+        Err(e) => {
+            log::warn!("Ignoring unreadable batch cache {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    if Utc::now() - cache.saved_at > max_age {
+        log::info!("Ignoring stale batch cache saved at {}", cache.saved_at);
+        return None;
+    }
+
+    Some((
+        cache.saved_at,
+        cache.batches.into_iter().map(Batch::from).collect(),
+    ))
+}
+
+/// save batches to the cache file, if one is configured
+pub async fn save_batches(batches: &[Batch]) -> Result<()> {
+    let path = match cache_file() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let cache = CacheFile {
+        saved_at: Utc::now(),
+        batches: batches.iter().map(CachedBatch::from).collect(),
+    };
+    let contents = serde_json::to_vec(&cache).expect("batch cache is serializable");
+
+    // write to a temporary file first so a partial write never replaces the cache
+    let partial = path.with_extension("partial");
+    tokio::fs::write(&partial, contents).await?;
+    tokio::fs::rename(&partial, &path).await?;
+    log::debug!("Saved {} batches to {:?}", batches.len(), path);
+
+    Ok(())
+}
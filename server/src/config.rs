@@ -0,0 +1,529 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use chrono::Duration;
+use log::LevelFilter;
+
+use crate::{
+    cache::CACHE_MAX_AGE, db::api::QuerySettings, reservation::DEFAULT_RESERVATION_TTL, Error,
+    Result,
+};
+
+/// Settings that are only read from the environment, mostly when the server
+/// starts, so they cannot be changed by the config file
+const RESTART_SETTINGS: [&str; 18] = [
+    "SNDB_AUTH",
+    "SNDB_USER",
+    "SNDB_PWD",
+    "SNDB_AAD_TOKEN",
+    "SNDB_QUERY_TIMEOUT_SECS",
+    "SNDB_CONN_MAX_LIFETIME_SECS",
+    "SN_PLANTS",
+    "SN_MAX_CONCURRENT_REQUESTS",
+    "SN_WRITE_API_KEY",
+    "SN_PRETTY_JSON",
+    "SN_LOWERCASE_PATHS",
+    "SN_DEBUG",
+    "SN_SCHEMA_CHECK_VERBOSE",
+    "SN_CACHE_FILE",
+    "SN_CONSUMPTION_FILE",
+    "SN_SIMTRANS_BUFFER_FILE",
+    "SN_NC_DIR",
+    "SN_NC_PENDING_FILE",
+];
+
+/// Restart settings of a plant's database, see [`crate::db::Plants`]
+///
+/// Each plant's are suffixed with its name, as `SNDB_HOST_<PLANT>`, and are
+/// not suffixed if `SN_PLANTS` is not set. `SNDB_CONNECTION_STRING` without
+/// a suffix is also used for every plant.
+const PLANT_SETTINGS: [&str; 6] = [
+    "SNDB_HOST",
+    "SNDB_DATABASE",
+    "SNDB_CONNECTION_STRING",
+    "SNDB_REPLICA_HOST",
+    "SNDB_REPLICA_DATABASE",
+    "SNDB_POOL_MAX_SIZE",
+];
+
+/// Settings whose values are never reported, including those of each plant
+///
+/// Connection strings may hold a password.
+const SECRET_SETTINGS: [&str; 4] = [
+    "SNDB_PWD",
+    "SNDB_AAD_TOKEN",
+    "SNDB_CONNECTION_STRING",
+    "SN_WRITE_API_KEY",
+];
+
+/// Reported in place of secret values that are set
+const REDACTED: &str = "********";
+
+/// Most programs listed for a machine, unless configured
+pub const DEFAULT_MAX_PROGRAMS: usize = 500;
+
+/// Column programs' cutting time is read from, unless configured
+pub const DEFAULT_CUTTING_TIME_COLUMN: &str = "CuttingTime";
+
+/// Interval completions are flushed from the SimTrans buffer at, unless configured
+pub const DEFAULT_SIMTRANS_BUFFER_INTERVAL_MS: i64 = 500;
+
+/// SimTrans transaction types completions may be posted as, unless configured
+pub const DEFAULT_SIMTRANS_TRANS_TYPES: [&str; 1] = ["SN70"];
+
+/// Response of `GET /batches` while no batches could be loaded yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchesEmptyMode {
+    /// fail with 503, the default
+    Strict,
+    /// an empty list, flagged as not loaded by the [`BATCHES_LOADED_HEADER`]
+    ///
+    /// [`BATCHES_LOADED_HEADER`]: crate::batch::BATCHES_LOADED_HEADER
+    Lenient,
+}
+
+impl std::str::FromStr for BatchesEmptyMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(Error::BadRequest(format!(
+                "Unknown batches empty mode `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+/// Config file, from env `SN_CONFIG_FILE`
+///
+/// The file has one `KEY=VALUE` setting per line, using the same keys as the
+/// environment. Reloadable settings in the file take precedence over the
+/// environment.
+pub fn config_file() -> Option<PathBuf> {
+    std::env::var_os("SN_CONFIG_FILE").map(PathBuf::from)
+}
+
+/// Server settings that can be reloaded while running
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `SN_LOG_LEVEL`
+    pub log_level: LevelFilter,
+    /// `SN_RESERVATION_TTL_SECS`
+    pub reservation_ttl: Duration,
+    /// `SN_CACHE_MAX_AGE_SECS`
+    pub cache_max_age: Duration,
+    /// `SN_SIMTRANS_DISTRICT`
+    pub simtrans_district: i32,
+    /// `SN_SIMTRANS_TRANS_TYPES`, comma separated transaction types completions
+    /// may be posted as
+    ///
+    /// The first type is used when a completion does not name one, and
+    /// programs with a completion of any of them are no longer queued. Checked
+    /// to be plain identifiers when loaded, as they are put in queries.
+    pub simtrans_trans_types: Vec<String>,
+    /// `SN_SIMTRANS_PROC`, stored procedure completions are posted through
+    ///
+    /// If not set, completions are inserted into `TransAct` directly.
+    pub simtrans_proc: Option<String>,
+    /// `SN_SIMTRANS_BUFFER_SIZE`, completions buffered before they are posted
+    /// together, see [`crate::buffer::SimTransBuffer`]
+    ///
+    /// If not set, each completion is posted as it is made.
+    pub simtrans_buffer_size: Option<usize>,
+    /// `SN_SIMTRANS_BUFFER_MS`, longest a completion stays buffered
+    pub simtrans_buffer_interval: Duration,
+    /// `SN_MAX_PROGRAMS`, most programs listed for a machine
+    pub max_programs: usize,
+    /// `SN_CACHE_REFRESH_SECS`, interval of background cache refreshes
+    ///
+    /// If not set, caches are only loaded when requested.
+    pub cache_refresh: Option<Duration>,
+    /// `SN_BATCHES_EMPTY_MODE`, `strict` or `lenient`
+    pub batches_empty_mode: BatchesEmptyMode,
+    /// `SN_CUTTING_TIME_COLUMN`, column the cutting time of programs is read
+    /// from, such as for estimated or standard times
+    ///
+    /// Every query of cutting time reads it, from `Program`, `ProgramMachine`
+    /// and `STPrgArc` alike. Checked to be a plain identifier when loaded, as
+    /// it is put in queries.
+    pub cutting_time_column: String,
+    /// `SN_MANAGED_MACHINES`, comma separated machines this server manages
+    ///
+    /// Other machines are not listed, and changes to them are forbidden, so
+    /// several servers can share a database. If not set, all machines are managed.
+    pub managed_machines: Option<Vec<String>>,
+    /// `SN_COMPLETE_GRACE_SECS`, time completed programs stay listed for their machine
+    ///
+    /// Defaults to 0, so programs leave the list as soon as they are completed.
+    pub complete_grace: Duration,
+
+    /// settings in the config file that differ from the environment but need a restart
+    deferred: Vec<String>,
+}
+
+impl Config {
+    /// load settings from the environment and config file
+    pub fn load() -> Result<Self> {
+        let mut settings = HashMap::new();
+        if let Some(path) = config_file() {
+            for line in std::fs::read_to_string(&path)?.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                match line.split_once('=') {
+                    Some((key, value)) => {
+                        settings.insert(key.trim().to_string(), value.trim().to_string())
+                    }
+                    None => {
+                        return Err(Error::BadRequest(format!(
+                            "Invalid line `{}` in config file {:?}",
+                            line, path
+                        )))
+                    }
+                };
+            }
+        }
+        let get = |key: &str| settings.get(key).cloned().or(std::env::var(key).ok());
+
+        let simtrans_trans_types: Vec<String> = match get("SN_SIMTRANS_TRANS_TYPES") {
+            Some(types) => types
+                .split(',')
+                .map(str::trim)
+                .filter(|trans_type| !trans_type.is_empty())
+                .map(Into::into)
+                .collect(),
+            None => DEFAULT_SIMTRANS_TRANS_TYPES.map(Into::into).to_vec(),
+        };
+        if simtrans_trans_types.is_empty() {
+            return Err(Error::BadRequest(
+                "SN_SIMTRANS_TRANS_TYPES must name at least one transaction type".into(),
+            ));
+        }
+        if let Some(trans_type) = simtrans_trans_types
+            .iter()
+            .find(|trans_type| !is_sql_identifier(trans_type))
+        {
+            return Err(Error::BadRequest(format!(
+                "Invalid transaction type `{}` in SN_SIMTRANS_TRANS_TYPES",
+                trans_type
+            )));
+        }
+
+        let simtrans_proc = get("SN_SIMTRANS_PROC").filter(|proc| !proc.is_empty());
+        if let Some(proc) = &simtrans_proc {
+            if proc.split('.').count() > 2 || !proc.split('.').all(is_sql_identifier) {
+                return Err(Error::BadRequest(format!(
+                    "Invalid value `{}` for SN_SIMTRANS_PROC, expected a procedure name",
+                    proc
+                )));
+            }
+        }
+
+        let simtrans_buffer_interval = match parse::<i64>(&get, "SN_SIMTRANS_BUFFER_MS")? {
+            Some(ms) if ms <= 0 => {
+                return Err(Error::BadRequest(format!(
+                    "Invalid value `{}` for SN_SIMTRANS_BUFFER_MS, expected a positive interval",
+                    ms
+                )))
+            }
+            ms => Duration::milliseconds(ms.unwrap_or(DEFAULT_SIMTRANS_BUFFER_INTERVAL_MS)),
+        };
+
+        let cutting_time_column =
+            get("SN_CUTTING_TIME_COLUMN").unwrap_or_else(|| DEFAULT_CUTTING_TIME_COLUMN.into());
+        if !is_sql_identifier(&cutting_time_column) {
+            return Err(Error::BadRequest(format!(
+                "Invalid value `{}` for SN_CUTTING_TIME_COLUMN, expected a column name",
+                cutting_time_column
+            )));
+        }
+
+        Ok(Self {
+            log_level: parse(&get, "SN_LOG_LEVEL")?.unwrap_or(LevelFilter::Trace),
+            reservation_ttl: parse(&get, "SN_RESERVATION_TTL_SECS")?
+                .map(Duration::seconds)
+                .unwrap_or(DEFAULT_RESERVATION_TTL),
+            cache_max_age: parse(&get, "SN_CACHE_MAX_AGE_SECS")?
+                .map(Duration::seconds)
+                .unwrap_or(CACHE_MAX_AGE),
+            simtrans_district: parse(&get, "SN_SIMTRANS_DISTRICT")?.unwrap_or(1),
+            simtrans_trans_types,
+            simtrans_proc,
+            simtrans_buffer_size: parse::<usize>(&get, "SN_SIMTRANS_BUFFER_SIZE")?
+                .filter(|&size| size > 0),
+            simtrans_buffer_interval,
+            max_programs: parse(&get, "SN_MAX_PROGRAMS")?.unwrap_or(DEFAULT_MAX_PROGRAMS),
+            cache_refresh: parse::<u64>(&get, "SN_CACHE_REFRESH_SECS")?
+                .filter(|&secs| secs > 0)
+                .map(|secs| Duration::seconds(secs as i64)),
+            batches_empty_mode: parse(&get, "SN_BATCHES_EMPTY_MODE")?
+                .unwrap_or(BatchesEmptyMode::Strict),
+            cutting_time_column,
+            managed_machines: get("SN_MANAGED_MACHINES").map(|machines| {
+                machines
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|machine| !machine.is_empty())
+                    .map(Into::into)
+                    .collect()
+            }),
+            complete_grace: parse::<u32>(&get, "SN_COMPLETE_GRACE_SECS")?
+                .map(|secs| Duration::seconds(secs as i64))
+                .unwrap_or_else(Duration::zero),
+            deferred: {
+                let mut deferred: Vec<String> = settings
+                    .iter()
+                    .filter(|(key, _)| restart_setting(key).is_some())
+                    .filter(|(key, value)| std::env::var(key).ok().as_ref() != Some(*value))
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                deferred.sort();
+                deferred
+            },
+        })
+    }
+
+    /// apply settings that take effect globally, such as the log level
+    pub fn apply(&self) {
+        log::set_max_level(self.log_level);
+    }
+
+    /// names of live settings that differ from another config
+    pub fn changed(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.log_level != other.log_level {
+            changed.push("SN_LOG_LEVEL");
+        }
+        if self.reservation_ttl != other.reservation_ttl {
+            changed.push("SN_RESERVATION_TTL_SECS");
+        }
+        if self.cache_max_age != other.cache_max_age {
+            changed.push("SN_CACHE_MAX_AGE_SECS");
+        }
+        if self.simtrans_district != other.simtrans_district {
+            changed.push("SN_SIMTRANS_DISTRICT");
+        }
+        if self.simtrans_trans_types != other.simtrans_trans_types {
+            changed.push("SN_SIMTRANS_TRANS_TYPES");
+        }
+        if self.simtrans_proc != other.simtrans_proc {
+            changed.push("SN_SIMTRANS_PROC");
+        }
+        if self.simtrans_buffer_size != other.simtrans_buffer_size {
+            changed.push("SN_SIMTRANS_BUFFER_SIZE");
+        }
+        if self.simtrans_buffer_interval != other.simtrans_buffer_interval {
+            changed.push("SN_SIMTRANS_BUFFER_MS");
+        }
+        if self.max_programs != other.max_programs {
+            changed.push("SN_MAX_PROGRAMS");
+        }
+        if self.cache_refresh != other.cache_refresh {
+            changed.push("SN_CACHE_REFRESH_SECS");
+        }
+        if self.batches_empty_mode != other.batches_empty_mode {
+            changed.push("SN_BATCHES_EMPTY_MODE");
+        }
+        if self.cutting_time_column != other.cutting_time_column {
+            changed.push("SN_CUTTING_TIME_COLUMN");
+        }
+        if self.managed_machines != other.managed_machines {
+            changed.push("SN_MANAGED_MACHINES");
+        }
+        if self.complete_grace != other.complete_grace {
+            changed.push("SN_COMPLETE_GRACE_SECS");
+        }
+
+        changed
+    }
+
+    /// values of live settings, by name
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("SN_LOG_LEVEL", self.log_level.to_string()),
+            (
+                "SN_RESERVATION_TTL_SECS",
+                self.reservation_ttl.num_seconds().to_string(),
+            ),
+            (
+                "SN_CACHE_MAX_AGE_SECS",
+                self.cache_max_age.num_seconds().to_string(),
+            ),
+            ("SN_SIMTRANS_DISTRICT", self.simtrans_district.to_string()),
+            (
+                "SN_SIMTRANS_TRANS_TYPES",
+                self.simtrans_trans_types.join(","),
+            ),
+            (
+                "SN_SIMTRANS_PROC",
+                self.simtrans_proc.clone().unwrap_or_default(),
+            ),
+            (
+                "SN_SIMTRANS_BUFFER_SIZE",
+                self.simtrans_buffer_size
+                    .map(|size| size.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "SN_SIMTRANS_BUFFER_MS",
+                self.simtrans_buffer_interval.num_milliseconds().to_string(),
+            ),
+            ("SN_MAX_PROGRAMS", self.max_programs.to_string()),
+            (
+                "SN_CACHE_REFRESH_SECS",
+                self.cache_refresh
+                    .map(|interval| interval.num_seconds().to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "SN_BATCHES_EMPTY_MODE",
+                format!("{:?}", self.batches_empty_mode).to_lowercase(),
+            ),
+            ("SN_CUTTING_TIME_COLUMN", self.cutting_time_column.clone()),
+            (
+                "SN_MANAGED_MACHINES",
+                self.managed_machines
+                    .as_ref()
+                    .map(|machines| machines.join(","))
+                    .unwrap_or_default(),
+            ),
+            (
+                "SN_COMPLETE_GRACE_SECS",
+                self.complete_grace.num_seconds().to_string(),
+            ),
+        ]
+    }
+
+    /// values of settings read when the server started, by name, with secrets redacted
+    ///
+    /// Settings that are not set have no value.
+    /// Settings of each plant are only listed if they are set.
+    pub fn restart_settings() -> Vec<(String, Option<String>)> {
+        let mut plants: Vec<String> = std::env::vars()
+            .map(|(key, _)| key)
+            .filter(|key| plant_setting(key).is_some())
+            .collect();
+        plants.sort();
+
+        RESTART_SETTINGS
+            .into_iter()
+            .chain(PLANT_SETTINGS)
+            .map(String::from)
+            .chain(plants)
+            .map(|key| {
+                let value = std::env::var(&key).ok();
+                match restart_setting(&key).is_some_and(|name| SECRET_SETTINGS.contains(&name)) {
+                    true => (key, value.map(|_| REDACTED.into())),
+                    false => (key, value),
+                }
+            })
+            .collect()
+    }
+
+    /// names of settings in the config file that are not applied until the
+    /// environment is updated and the server restarted
+    pub fn deferred(&self) -> &[String] {
+        &self.deferred
+    }
+
+    /// check that completions may be posted as a transaction type, defaulting
+    /// to the first configured type
+    ///
+    /// The stored procedure of [`Self::simtrans_proc`] is not given the type,
+    /// so only the first type is allowed while it is set.
+    pub fn simtrans_trans_type(&self, trans_type: Option<&str>) -> Result<String> {
+        let default = &self.simtrans_trans_types[0];
+        match trans_type {
+            None => Ok(default.clone()),
+            Some(trans_type) if self.simtrans_proc.is_some() && trans_type != default => {
+                Err(Error::BadRequest(format!(
+                    "Transaction type `{}` is not allowed, completions posted through \
+                     SN_SIMTRANS_PROC are always {}",
+                    trans_type, default
+                )))
+            }
+            Some(trans_type) if self.simtrans_trans_types.iter().any(|t| t == trans_type) => {
+                Ok(trans_type.into())
+            }
+            Some(trans_type) => Err(Error::BadRequest(format!(
+                "Transaction type `{}` is not allowed, expected one of {}",
+                trans_type,
+                self.simtrans_trans_types.join(", ")
+            ))),
+        }
+    }
+
+    /// settings put in database queries
+    pub fn query_settings(&self) -> QuerySettings {
+        QuerySettings {
+            cutting_time: self.cutting_time_column.clone(),
+            trans_types: self.simtrans_trans_types.clone(),
+        }
+    }
+
+    /// machine is managed by this server, see [`Self::managed_machines`]
+    pub fn is_managed(&self, machine: &str) -> bool {
+        match &self.managed_machines {
+            Some(machines) => machines.iter().any(|managed| managed == machine),
+            None => true,
+        }
+    }
+
+    /// check that a machine is managed by this server, failing with [`Error::Forbidden`] if not
+    pub fn check_managed(&self, machine: &str) -> Result<()> {
+        match self.is_managed(machine) {
+            true => Ok(()),
+            false => Err(Error::Forbidden(format!(
+                "Machine {} is not managed by this server",
+                machine
+            ))),
+        }
+    }
+}
+
+/// name of the restart setting `key` is, without the plant it is for, if any
+fn restart_setting(key: &str) -> Option<&'static str> {
+    RESTART_SETTINGS
+        .into_iter()
+        .chain(PLANT_SETTINGS)
+        .find(|&name| name == key)
+        .or_else(|| plant_setting(key))
+}
+
+/// name of the plant setting `key` is, if it is one for a single plant
+fn plant_setting(key: &str) -> Option<&'static str> {
+    PLANT_SETTINGS.into_iter().find(|name| {
+        key.strip_prefix(name)
+            .and_then(|plant| plant.strip_prefix('_'))
+            .is_some_and(|plant| !plant.is_empty())
+    })
+}
+
+/// check that a name is a plain SQL identifier, of only ASCII letters, digits
+/// and `_`, not starting with a digit
+///
+/// Procedure and column names, and transaction types matched against a list,
+/// cannot be query parameters, so configured names are checked before being
+/// put in a query. Anything that could end the name and inject SQL, such as
+/// quotes, brackets, spaces or `;`, is rejected.
+fn is_sql_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse<T: std::str::FromStr>(
+    get: &impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<T>> {
+    get(key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| Error::BadRequest(format!("Invalid value `{}` for {}", value, key)))
+        })
+        .transpose()
+}
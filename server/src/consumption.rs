@@ -0,0 +1,104 @@
+//! Ledger of sheets consumed from batches by completed programs
+//!
+//! Completions take sheets from the cached batches, but the batch source is
+//! only updated when it is exported again. Consumption is recorded here so it
+//! is taken off the batches again when they are reloaded from a source that
+//! does not reflect it yet.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::batch::Batch;
+
+/// File the ledger is saved to, from env `SN_CONSUMPTION_FILE`
+///
+/// Defaults to `batch_consumption.json` in the working directory, so
+/// consumption recorded before a restart is still applied after it.
+pub fn ledger_file() -> PathBuf {
+    std::env::var_os("SN_CONSUMPTION_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("batch_consumption.json"))
+}
+
+/// Sheets taken from a batch by a completed program
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Consumption {
+    pub batch: String,
+    pub sheets: u32,
+    pub consumed_at: DateTime<Utc>,
+}
+
+/// Consumption not yet reflected by the batch source, saved to the
+/// [`ledger_file`] on every change
+#[derive(Debug, Default)]
+pub struct ConsumptionLedger(Vec<Consumption>);
+
+impl ConsumptionLedger {
+    /// load the consumption saved by the last run, if any
+    pub async fn load() -> Self {
+        let path = ledger_file();
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        match serde_json::from_slice(&contents) {
+            Ok(consumed) => Self(consumed),
+            Err(e) => {
+                log::warn!("Ignoring unreadable batch consumption {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    async fn save(&self) {
+        let contents = serde_json::to_vec(&self.0).expect("batch consumption is serializable");
+        if let Err(e) = tokio::fs::write(ledger_file(), contents).await {
+            log::error!("Failed to save batch consumption");
+            log::error!("{:#?}", e);
+        }
+    }
+
+    /// record sheets taken from a batch
+    pub async fn record(&mut self, batch: &str, sheets: u32) {
+        self.0.push(Consumption {
+            batch: batch.into(),
+            sheets,
+            consumed_at: Utc::now(),
+        });
+        self.save().await;
+    }
+
+    /// take the recorded consumption off batches loaded from the batch source
+    ///
+    /// Consumption recorded before the source was last modified is already
+    /// reflected by it, so it is dropped from the ledger instead. Exhausted
+    /// batches are removed.
+    pub async fn apply(
+        &mut self,
+        batches: &mut Vec<Batch>,
+        source_modified: Option<DateTime<Utc>>,
+    ) {
+        if let Some(modified) = source_modified {
+            let recorded = self.0.len();
+            self.0.retain(|consumed| consumed.consumed_at > modified);
+            if self.0.len() < recorded {
+                log::debug!(
+                    "Dropped {} batch consumptions reflected by the batch source",
+                    recorded - self.0.len()
+                );
+                self.save().await;
+            }
+        }
+
+        for consumed in &self.0 {
+            if let Some(batch) = batches.iter_mut().find(|bat| bat.id == consumed.batch) {
+                batch.consume(consumed.sheets);
+            }
+        }
+        batches.retain(|batch| batch.qty > 0 || !self.0.iter().any(|c| c.batch == batch.id));
+    }
+}
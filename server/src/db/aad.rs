@@ -0,0 +1,103 @@
+use std::{
+    io::{Error, Result},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+
+/// Azure Instance Metadata Service, which issues managed identity tokens
+const IMDS_HOST: &str = "169.254.169.254";
+
+/// Resource the token is requested for
+const SQL_RESOURCE: &str = "https://database.windows.net/";
+
+/// Time the instance metadata service has to issue a token
+const IMDS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tokens are refreshed this long before they expire, so a connection made
+/// just before does not authenticate with an expired token
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Lifetime assumed for tokens issued without `expires_in`
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Last token issued by the managed identity
+struct CachedToken {
+    token: String,
+    expires_at: Instant,
+}
+
+static TOKEN: Mutex<Option<CachedToken>> = Mutex::const_new(None);
+
+/// get an Azure AD access token for the database
+///
+/// Uses env `SNDB_AAD_TOKEN` if set, otherwise requests a token for the
+/// machine's managed identity from the instance metadata service.
+/// Managed identity tokens are cached, and requested again shortly before
+/// they expire, so every new connection authenticates with a valid token.
+/// A token from `SNDB_AAD_TOKEN` cannot be refreshed.
+pub async fn aad_token() -> Result<String> {
+    if let Ok(token) = std::env::var("SNDB_AAD_TOKEN") {
+        log::debug!("using AAD token from SNDB_AAD_TOKEN");
+        return Ok(token);
+    }
+
+    let mut cached = TOKEN.lock().await;
+    if let Some(cached) = cached
+        .as_ref()
+        .filter(|cached| cached.expires_at > Instant::now() + REFRESH_MARGIN)
+    {
+        return Ok(cached.token.clone());
+    }
+
+    log::debug!("requesting AAD token from managed identity");
+    let (token, lifetime) = request_token().await?;
+
+    *cached = Some(CachedToken {
+        token: token.clone(),
+        expires_at: Instant::now() + lifetime,
+    });
+    Ok(token)
+}
+
+/// request a token from the instance metadata service, with its lifetime
+async fn request_token() -> Result<(String, Duration)> {
+    let client = reqwest::Client::builder()
+        .timeout(IMDS_TIMEOUT)
+        .build()
+        .map_err(Error::other)?;
+    let response = client
+        .get(format!(
+            "http://{}/metadata/identity/oauth2/token",
+            IMDS_HOST
+        ))
+        .query(&[("api-version", "2018-02-01"), ("resource", SQL_RESOURCE)])
+        .header("Metadata", "true")
+        .send()
+        .await
+        .map_err(Error::other)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::other(format!(
+            "managed identity returned {}",
+            status
+        )));
+    }
+
+    let body: serde_json::Value = response.json().await.map_err(Error::other)?;
+    let token = body["access_token"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| Error::other("managed identity response has no access_token"))?;
+
+    // IMDS sends `expires_in` as a string of seconds
+    let lifetime = match &body["expires_in"] {
+        serde_json::Value::String(secs) => secs.parse().ok(),
+        secs => secs.as_u64(),
+    }
+    .map(Duration::from_secs)
+    .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+
+    Ok((token, lifetime))
+}
@@ -1,4 +1,5 @@
 use super::{Nest, Program};
+use crate::{db::SqlConn, Error};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,7 +39,7 @@ where
 
     fn try_from(row: &'a tiberius::Row) -> crate::Result<TransactionType<T>> {
         match row.try_get::<&str, _>("TransType")? {
-            Some("SN100") => Ok(Self::Created(T::try_from(&row)?)),
+            Some("SN100") => Ok(Self::Created(T::try_from(row)?)),
             Some("SN101") => Ok(Self::Deleted),
             Some("SN102") => Ok(Self::Updated),
             _ => unreachable!(),
@@ -55,11 +56,107 @@ impl<T, O> PartialEq<TransactionType<O>> for TransactionType<T> {
     }
 }
 
+/// Review status of a feedback entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Open,
+    Reviewed,
+    Resolved,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Open => "open",
+            Resolution::Reviewed => "reviewed",
+            Resolution::Resolved => "resolved",
+        }
+    }
+}
+
+impl std::str::FromStr for Resolution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "open" => Ok(Resolution::Open),
+            "reviewed" => Ok(Resolution::Reviewed),
+            "resolved" => Ok(Resolution::Resolved),
+            _ => Err(Error::BadRequest(format!(
+                "Unknown feedback resolution `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a tiberius::Row> for Resolution {
+    type Error = crate::Error;
+
+    fn try_from(row: &'a tiberius::Row) -> crate::Result<Resolution> {
+        // entries without a stored resolution have not been reviewed
+        match row.try_get::<&str, _>("Resolution")? {
+            None | Some("open") => Ok(Resolution::Open),
+            Some("reviewed") => Ok(Resolution::Reviewed),
+            Some("resolved") => Ok(Resolution::Resolved),
+            Some(other) => {
+                log::warn!("Unknown feedback resolution `{}`, treating as open", other);
+                Ok(Resolution::Open)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FeedbackEntry<T> {
     pub archive_packet_id: i32,
     pub state: TransactionType<T>,
+    pub resolution: Resolution,
+}
+
+impl FeedbackEntry<()> {
+    /// set the resolution of a feedback entry
+    pub async fn resolve(
+        conn: &mut SqlConn<'_>,
+        archive_packet_id: i32,
+        resolution: Resolution,
+    ) -> crate::Result<()> {
+        let found: i32 = conn
+            .query(
+                "select count(*) from STPrgArc where ArchivePacketID=@P1",
+                &[&archive_packet_id],
+            )
+            .await?
+            .into_row()
+            .await?
+            .and_then(|row| row.get(0))
+            .unwrap_or_default();
+
+        if found == 0 {
+            return Err(Error::NotFound(format!(
+                "Feedback entry {} not found",
+                archive_packet_id
+            )));
+        }
+
+        conn.execute(
+            r#"
+merge FeedbackResolution as target
+using (select @P1 as ArchivePacketID, @P2 as Status) as source
+on target.ArchivePacketID=source.ArchivePacketID
+when matched then
+	update set Status=source.Status, ResolvedAt=sysdatetime()
+when not matched then
+	insert (ArchivePacketID, Status) values (source.ArchivePacketID, source.Status);
+        "#,
+            &[&archive_packet_id, &resolution.as_str()],
+        )
+        .await?;
+
+        Ok(())
+    }
 }
 
 impl<'a, T> TryFrom<&'a tiberius::Row> for FeedbackEntry<T>
@@ -73,6 +170,7 @@ where
         Ok(Self {
             archive_packet_id: row.try_get("ArchivePacketID")?.unwrap(),
             state: TransactionType::try_from(row)?,
+            resolution: Resolution::try_from(row)?,
         })
     }
 }
@@ -91,6 +189,7 @@ impl From<FeedbackEntry<Program>> for FeedbackEntry<Nest> {
         FeedbackEntry {
             archive_packet_id: value.archive_packet_id,
             state,
+            resolution: value.resolution,
         }
     }
 }
@@ -0,0 +1,71 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::SqlConn, Error, Result};
+
+/// Program hidden from its machine's queue, such as a template or test nest
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HiddenProgram {
+    pub program: String,
+    pub operator: Option<String>,
+    pub hidden_at: NaiveDateTime,
+}
+
+impl HiddenProgram {
+    /// hide a program from its machine's queue
+    ///
+    /// Hiding a program that is already hidden keeps who hid it first.
+    pub async fn hide(conn: &mut SqlConn<'_>, program: &str, operator: &str) -> Result<Self> {
+        let row = conn
+            .query(
+                r#"
+if exists (select 1 from Program where ProgramName=@P1)
+	and not exists (select 1 from HiddenProgram where ProgramName=@P1)
+insert into HiddenProgram(ProgramName, Operator)
+values (@P1, @P2);
+select ProgramName, Operator, HiddenAt
+from HiddenProgram
+where ProgramName=@P1;
+        "#,
+                &[&program, &operator],
+            )
+            .await?
+            .into_row()
+            .await?;
+
+        match row {
+            Some(row) => Self::try_from(&row),
+            None => Err(Error::NotFound(format!("Program {} not found", program))),
+        }
+    }
+
+    /// show a hidden program in its machine's queue again
+    ///
+    /// Returns `false` if the program was not hidden.
+    pub async fn unhide(conn: &mut SqlConn<'_>, program: &str) -> Result<bool> {
+        let result = conn
+            .execute(
+                "delete from HiddenProgram where ProgramName=@P1",
+                &[&program],
+            )
+            .await?;
+
+        Ok(result.total() > 0)
+    }
+}
+
+impl TryFrom<&tiberius::Row> for HiddenProgram {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            program: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            operator: row.try_get::<&str, _>("Operator")?.map(Into::into),
+            hidden_at: row.try_get("HiddenAt")?.unwrap(),
+        })
+    }
+}
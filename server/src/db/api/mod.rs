@@ -1,16 +1,31 @@
 mod feedback;
+mod hidden;
 mod nest;
 mod part;
+mod priority;
 mod program;
 mod remnant;
 mod sheet;
+pub mod simtrans;
+mod state;
+mod throughput;
+mod timing;
 
-pub use feedback::{FeedbackEntry, TransactionType};
+pub use feedback::{FeedbackEntry, Resolution, TransactionType};
+pub use hidden::HiddenProgram;
 pub use nest::Nest;
 pub use part::Part;
-pub use program::Program;
-pub use remnant::Remnant;
-pub use sheet::Sheet;
+pub use priority::ProgramPriority;
+pub use program::{
+    MachineProgram, Program, QueueEstimate, QueuePosition, QueuedProgram, RelatedProgram,
+    SharedPart, WorkOrderProgram,
+};
+pub use remnant::{Remnant, RemnantEstimate};
+pub use sheet::{BoundingBox, Sheet, Utilization};
+pub use simtrans::{PendingSimTrans, PostedTransaction};
+pub use state::{ProgramState, ProgramStatus, QueueChange, QueueChangeKind, StateLogEntry};
+pub use throughput::{MachineThroughput, ThroughputDay};
+pub use timing::{iso8601_duration, ProgramTiming};
 
 pub fn get<'a, T>(row: &'a tiberius::Row, aliases: &[&str]) -> crate::Result<T>
 where
@@ -27,3 +42,172 @@ where
         aliases
     )))
 }
+
+/// Site settings that are put in queries, as they cannot be query parameters
+///
+/// Templates name them as `{cutting_time}` and `{trans_types}` placeholders,
+/// filled by [`Self::render`].
+#[derive(Debug, Clone)]
+pub struct QuerySettings {
+    /// column of program cutting time, checked to be a plain identifier, see
+    /// [`crate::config::Config::cutting_time_column`]
+    pub cutting_time: String,
+    /// SimTrans transaction types completions are posted as, checked to be
+    /// plain identifiers, see [`crate::config::Config::simtrans_trans_types`]
+    pub trans_types: Vec<String>,
+}
+
+impl QuerySettings {
+    /// fill the placeholders of a query template
+    pub fn render(&self, sql: &str) -> String {
+        let trans_types: Vec<String> = self
+            .trans_types
+            .iter()
+            .map(|trans_type| format!("'{}'", trans_type))
+            .collect();
+
+        sql.replace("{cutting_time}", &self.cutting_time)
+            .replace("{trans_types}", &trans_types.join(", "))
+    }
+}
+
+/// SQL behind the endpoints, with their `@P` parameter placeholders
+pub fn queries(settings: &QuerySettings) -> Vec<(&'static str, String)> {
+    let queries = vec![
+        ("GET /:machine", MachineProgram::BY_MACHINE_SQL),
+        ("GET /programs/unmatched", QueuedProgram::QUEUED_SQL),
+        ("GET /programs/by-state/:state", StateLogEntry::IN_STATE_SQL),
+        ("GET /nest/:nest", Nest::GET_SQL),
+        ("GET /nest/:nest/status", ProgramStatus::GET_SQL),
+        (
+            "GET /nest/:nest/status (state log)",
+            StateLogEntry::LATEST_SQL,
+        ),
+        ("GET /nest/:nest/timing", ProgramTiming::GET_SQL),
+        ("GET /nest/:nest/position", QueuePosition::GET_SQL),
+        ("GET /nest/:nest/estimate", QueueEstimate::GET_SQL),
+        ("GET /nest/:nest/priority", ProgramPriority::GET_SQL),
+        ("GET /materials/:material/demand", Sheet::QUEUED_REPEATS_SQL),
+        ("GET /snapshot/diff", QueueChange::BETWEEN_SQL),
+        ("GET /simtrans/transactions", PostedTransaction::RANGE_SQL),
+        ("GET /reports/throughput", MachineThroughput::RANGE_SQL),
+        (
+            "GET /workorders/:wo/programs",
+            WorkOrderProgram::BY_WORK_ORDER_SQL,
+        ),
+    ];
+
+    queries
+        .into_iter()
+        .map(|(endpoint, sql)| (endpoint, settings.render(sql)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn feedback_nest_serializes_as_camel_case() {
+        let entry = FeedbackEntry {
+            archive_packet_id: 7,
+            state: TransactionType::Created(Nest {
+                archive_packet_id: 7,
+                program: Program {
+                    program_name: "1200X-01".into(),
+                    repeat_id: 1,
+                    machine_name: "Gemini".into(),
+                    cutting_time: 90.0,
+                },
+                parts: vec![Part {
+                    part_name: "1200X-01A-X1".into(),
+                    part_qty: 2,
+                    job: "1200X-01".into(),
+                    shipment: 1,
+                    true_area: 10.0,
+                    nested_area: 12.0,
+                }],
+                sheet: Sheet {
+                    sheet_name: "S1234".into(),
+                    material_master: "50/50W-0500".into(),
+                    is_singleton: true,
+                },
+                remnants: vec![Remnant {
+                    remnant_name: "R1234".into(),
+                    length: 40.0,
+                    width: 20.0,
+                    area: 800.0,
+                }],
+            }),
+            resolution: Resolution::Open,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            json!({
+                "archivePacketId": 7,
+                "state": {
+                    "created": {
+                        "archivePacketId": 7,
+                        "program": {
+                            "programName": "1200X-01",
+                            "repeatId": 1,
+                            "machineName": "Gemini",
+                            "cuttingTime": 90.0,
+                        },
+                        "parts": [{
+                            "partName": "1200X-01A-X1",
+                            "partQty": 2,
+                            "job": "1200X-01",
+                            "shipment": 1,
+                            "trueArea": 10.0,
+                            "nestedArea": 12.0,
+                        }],
+                        "sheet": {
+                            "sheetName": "S1234",
+                            "materialMaster": "50/50W-0500",
+                            "isSingleton": true,
+                        },
+                        "remnants": [{
+                            "remnantName": "R1234",
+                            "length": 40.0,
+                            "width": 20.0,
+                            "area": 800.0,
+                        }],
+                    }
+                },
+                "resolution": "open",
+            })
+        );
+    }
+
+    #[test]
+    fn machine_program_serializes_as_camel_case() {
+        let program = MachineProgram {
+            program: "1200X-01".into(),
+            repeats: 2,
+            cutting_time: 90.0,
+            cutting_time_iso: "PT1M30S".into(),
+            due_date: None,
+            priority: 0,
+            just_completed: false,
+            hidden: false,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&program).unwrap(),
+            json!({
+                "program": "1200X-01",
+                "repeats": 2,
+                "cuttingTime": 90.0,
+                "cuttingTimeIso": "PT1M30S",
+                "dueDate": null,
+                "priority": 0,
+                "justCompleted": false,
+                "hidden": false,
+            })
+        );
+    }
+}
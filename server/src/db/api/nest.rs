@@ -1,7 +1,7 @@
 use crate::{Error, Result};
 
 use super::super::SqlConn;
-use super::{Part, Program, Remnant, Sheet};
+use super::{Part, Program, QuerySettings, Remnant, Sheet};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,16 +15,55 @@ pub struct Nest {
 }
 
 impl Nest {
-    pub async fn get(conn: &mut SqlConn<'_>, nest: &String) -> crate::Result<Self> {
-        // TODO: seems to work for now, but should refactor find by program
-        let mut results = conn
-            .query(
-                r#"
+    /// top-level fields of a serialized nest, as selected by [`Self::select_fields`]
+    pub const FIELDS: [&'static str; 5] =
+        ["archivePacketId", "program", "parts", "sheet", "remnants"];
+
+    /// parse a comma separated list of fields, such as `sheet,parts,program`
+    ///
+    /// Fails with [`Error::BadRequest`] if a field is not one of [`Self::FIELDS`].
+    pub fn parse_fields(fields: &str) -> Result<Vec<String>> {
+        let fields: Vec<String> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(String::from)
+            .collect();
+
+        let unknown: Vec<&str> = fields
+            .iter()
+            .map(String::as_str)
+            .filter(|field| !Self::FIELDS.contains(field))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(Error::BadRequest(format!(
+                "Unknown nest fields `{}`, expected any of `{}`",
+                unknown.join(","),
+                Self::FIELDS.join(",")
+            )));
+        }
+
+        Ok(fields)
+    }
+
+    /// serialize the nest with only the given top-level fields
+    pub fn select_fields(&self, fields: &[String]) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap();
+        if let Some(object) = value.as_object_mut() {
+            object.retain(|key, _| fields.contains(key));
+        }
+
+        value
+    }
+
+    /// query of [`Self::get`]
+    pub const GET_SQL: &str = r#"
 select
 	ProgramName, RepeatID, ArchivePacketID,
-	MachineName, CuttingTime
+	MachineName, [{cutting_time}] as CuttingTime
 from Program
-where ProgramName=@P1;
+where ProgramName=@P1
+and (@P2 is null or RepeatID=@P2);
 select distinct
 	ProgramName,
 	PIP.WONumber, PIP.PartName, QtyInProcess as Qty,
@@ -34,7 +73,8 @@ from PIP
 inner join Part on PIP.PartName=Part.PartName
 where ProgramName=@P1;
 select distinct
-	Stock.SheetName, PrimeCode as MaterialMaster
+	Stock.SheetName, PrimeCode as MaterialMaster,
+	cast(iif(Stock.SheetName<>PrimeCode, 1, 0) as bit) as IsSingleton
 from Stock
 inner join Program on Stock.SheetName=Program.SheetName
 where ProgramName=@P1;
@@ -44,9 +84,28 @@ select distinct
 	PrimeCode, Qty
 from Remnant
 where ProgramName=@P1;
-    "#,
-                &[nest],
-            )
+    "#;
+
+    pub async fn get(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        nest: &String,
+    ) -> crate::Result<Self> {
+        Self::get_repeat(conn, settings, nest, None).await
+    }
+
+    /// get a nest with the program of one repeat, or of the first repeat if `None`
+    ///
+    /// Fails with [`Error::NotFound`] if the program does not have the repeat.
+    pub async fn get_repeat(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        nest: &String,
+        repeat: Option<i32>,
+    ) -> crate::Result<Self> {
+        // TODO: seems to work for now, but should refactor find by program
+        let mut results = conn
+            .query(settings.render(Self::GET_SQL), &[nest, &repeat])
             .await?
             .into_results()
             .await
@@ -57,12 +116,15 @@ where ProgramName=@P1;
             .into_iter();
 
         let (archive_packet_id, program) = match results.next() {
-            Some(mut programs) if programs.len() > 0 => {
+            Some(mut programs) if !programs.is_empty() => {
                 let p = programs.swap_remove(0);
                 Program::try_from(&p).map(|prg| (p.get::<i32, _>("ArchivePacketID").unwrap(), prg))
             }
             _ => {
-                return Err(Error::NotFound(format!("Program {} not found", nest)));
+                return Err(Error::NotFound(match repeat {
+                    Some(repeat) => format!("Repeat {} of program {} not found", repeat, nest),
+                    None => format!("Program {} not found", nest),
+                }));
             }
         }?;
 
@@ -70,11 +132,11 @@ where ProgramName=@P1;
             .next()
             .unwrap()
             .iter()
-            .map(|row| Part::try_from(row))
+            .map(Part::try_from)
             .collect::<Result<Vec<Part>>>()?;
 
         let sheet = match results.next() {
-            Some(mut sheets) if sheets.len() > 0 => {
+            Some(mut sheets) if !sheets.is_empty() => {
                 sheets.pop().map(|row| Sheet::try_from(&row)).unwrap()?
             }
             _ => {
@@ -88,7 +150,7 @@ where ProgramName=@P1;
         let remnants = match results.next() {
             Some(rems) => rems
                 .iter()
-                .map(|row| Remnant::try_from(row))
+                .map(Remnant::try_from)
                 .collect::<Result<Vec<Remnant>>>()?,
             None => Vec::new(),
         };
@@ -20,16 +20,18 @@ impl Part {
         conn.simple_query(
             r#"
 select
-	ArchivePacketID,
+	STPIPArc.ArchivePacketID,
     TransType,
 	STPIPArc.PartName,
     QtyInProcess as Qty,
     Data1 as Job,
     cast(Data2 as int) as Shipment,
 	TrueArea,
-    NestedArea
+    NestedArea,
+    FeedbackResolution.Status as Resolution
 from STPIPArc
-inner join Part on Part.PartName=STPIPArc.PartName and Part.WONumber=STPIPArc.WONumber;
+inner join Part on Part.PartName=STPIPArc.PartName and Part.WONumber=STPIPArc.WONumber
+left join FeedbackResolution on FeedbackResolution.ArchivePacketID=STPIPArc.ArchivePacketID;
         "#,
         )
         .await?
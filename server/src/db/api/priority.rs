@@ -0,0 +1,86 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::SqlConn, Error, Result};
+
+/// Priority of a program, set by expediters to move it up its machine's queue
+///
+/// Programs without a priority set have priority 0.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramPriority {
+    pub program: String,
+    pub priority: i32,
+    pub operator: Option<String>,
+    pub set_at: Option<NaiveDateTime>,
+}
+
+impl ProgramPriority {
+    /// query of [`Self::get`]
+    pub const GET_SQL: &str = r#"
+select top 1
+	Program.ProgramName,
+	isnull(pri.Priority, 0) as Priority,
+	pri.Operator, pri.SetAt
+from Program
+left join ProgramPriority as pri on pri.ProgramName=Program.ProgramName
+where Program.ProgramName=@P1
+        "#;
+
+    /// get the priority of a program
+    pub async fn get(conn: &mut SqlConn<'_>, program: &str) -> Result<Self> {
+        match conn
+            .query(Self::GET_SQL, &[&program])
+            .await?
+            .into_row()
+            .await?
+        {
+            Some(row) => Self::try_from(&row),
+            None => Err(Error::NotFound(format!("Program {} not found", program))),
+        }
+    }
+
+    /// set the priority of a program, replacing any set before
+    pub async fn set(
+        conn: &mut SqlConn<'_>,
+        program: &str,
+        priority: i32,
+        operator: &str,
+    ) -> Result<Self> {
+        // fails with not found before anything is written
+        Self::get(conn, program).await?;
+
+        conn.execute(
+            r#"
+merge ProgramPriority as target
+using (select @P1 as ProgramName, @P2 as Priority, @P3 as Operator) as source
+on target.ProgramName=source.ProgramName
+when matched then
+	update set Priority=source.Priority, Operator=source.Operator, SetAt=sysdatetime()
+when not matched then
+	insert (ProgramName, Priority, Operator)
+	values (source.ProgramName, source.Priority, source.Operator);
+        "#,
+            &[&program, &priority, &operator],
+        )
+        .await?;
+
+        Self::get(conn, program).await
+    }
+}
+
+impl TryFrom<&tiberius::Row> for ProgramPriority {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            program: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            priority: row.try_get("Priority")?.unwrap_or_default(),
+            operator: row.try_get::<&str, _>("Operator")?.map(Into::into),
+            set_at: row.try_get("SetAt")?,
+        })
+    }
+}
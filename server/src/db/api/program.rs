@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, Local, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 
-use super::FeedbackEntry;
-use crate::{db::SqlConn, Result};
+use super::{iso8601_duration, FeedbackEntry, QuerySettings, Sheet};
+use crate::{db::SqlConn, machine::MachineName, Error, Result};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -14,16 +17,21 @@ pub struct Program {
 
 impl Program {
     /// get in process and updated programs from feedback
-    pub async fn get_feedback(conn: &mut SqlConn<'_>) -> Result<Vec<FeedbackEntry<Self>>> {
-        conn.simple_query(
+    pub async fn get_feedback(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+    ) -> Result<Vec<FeedbackEntry<Self>>> {
+        conn.simple_query(settings.render(
             r#"
 select
 	ProgramName, RepeatID,
-	ArchivePacketID, TransType,
-	MachineName, CuttingTime
-from STPrgArc;
+	STPrgArc.ArchivePacketID, TransType,
+	MachineName, [{cutting_time}] as CuttingTime,
+	FeedbackResolution.Status as Resolution
+from STPrgArc
+left join FeedbackResolution on FeedbackResolution.ArchivePacketID=STPrgArc.ArchivePacketID;
         "#,
-        )
+        ))
         .await?
         .into_first_result()
         .await?
@@ -33,6 +41,103 @@ from STPrgArc;
     }
 }
 
+impl Program {
+    /// get the machine a program is queued on, if it is on one
+    pub async fn get_machine(conn: &mut SqlConn<'_>, program: &str) -> Result<Option<String>> {
+        Ok(conn
+            .query(
+                "select top 1 MachineName from ProgramMachine where ProgramName=@P1",
+                &[&program],
+            )
+            .await?
+            .into_row()
+            .await?
+            .and_then(|row| row.get::<&str, _>("MachineName").map(Into::into)))
+    }
+
+    /// get the other programs nested on the same sheet as a program
+    pub async fn get_siblings(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        program: &str,
+        sheet_name: &str,
+    ) -> Result<Vec<Self>> {
+        conn.query(
+            settings.render(
+                r#"
+select
+	ProgramName, RepeatID,
+	MachineName, [{cutting_time}] as CuttingTime
+from Program
+where SheetName=@P2 and ProgramName<>@P1
+order by ProgramName;
+        "#,
+            ),
+            &[&program, &sheet_name],
+        )
+        .await?
+        .into_first_result()
+        .await?
+        .iter()
+        .map(Self::try_from)
+        .collect()
+    }
+
+    /// move a program to another machine's queue, returning the machine it was on
+    pub async fn reassign_machine(
+        conn: &mut SqlConn<'_>,
+        program: &str,
+        machine: &MachineName,
+    ) -> Result<String> {
+        let mut results = conn
+            .query(
+                r#"
+select top 1
+	MachineName
+from ProgramMachine
+where ProgramName=@P1;
+select
+	count(*) as Machines
+from ProgramMachine
+where MachineName=@P2;
+        "#,
+                &[&program, &machine.as_str()],
+            )
+            .await?
+            .into_results()
+            .await?
+            .into_iter();
+
+        let current: String = match results.next().and_then(|mut rows| rows.pop()) {
+            Some(row) => row
+                .try_get::<&str, _>("MachineName")?
+                .map(Into::into)
+                .unwrap_or_default(),
+            None => return Err(Error::NotFound(format!("Program {} not found", program))),
+        };
+
+        let machines = match results.next().and_then(|mut rows| rows.pop()) {
+            Some(row) => row.try_get::<i32, _>("Machines")?.unwrap_or_default(),
+            None => 0,
+        };
+        if machines == 0 {
+            return Err(Error::NotFound(format!("Machine {} not found", machine)));
+        }
+
+        conn.execute(
+            r#"
+update ProgramMachine
+set MachineName=@P2
+where ProgramName=@P1;
+        "#,
+            &[&program, &machine.as_str()],
+        )
+        .await?;
+
+        Ok(current)
+    }
+}
+
 impl TryFrom<&tiberius::Row> for Program {
     type Error = crate::Error;
 
@@ -51,3 +156,625 @@ impl TryFrom<&tiberius::Row> for Program {
         })
     }
 }
+
+/// Program with repeats that have not been completed, and the sheet it is nested on
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedProgram {
+    pub program_name: String,
+    pub machine_name: String,
+    pub sheet: Sheet,
+}
+
+impl QueuedProgram {
+    /// query of [`Self::get_all`]
+    pub const QUEUED_SQL: &str = r#"
+select distinct
+	Program.ProgramName, Program.MachineName,
+	Stock.SheetName, PrimeCode as MaterialMaster,
+	cast(iif(Stock.SheetName<>PrimeCode, 1, 0) as bit) as IsSingleton
+from Program
+inner join Stock on Stock.SheetName=Program.SheetName
+where not exists (
+	select 1
+	from TransAct
+	where TransType in ({trans_types})
+	and TransAct.ProgramName=Program.ProgramName
+	and TransAct.ProgramRepeat=Program.RepeatId
+);
+        "#;
+
+    /// get programs that have not been completed on any machine
+    pub async fn get_all(conn: &mut SqlConn<'_>, settings: &QuerySettings) -> Result<Vec<Self>> {
+        conn.simple_query(settings.render(Self::QUEUED_SQL))
+            .await?
+            .into_first_result()
+            .await?
+            .iter()
+            .map(Self::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<&tiberius::Row> for QueuedProgram {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            program_name: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            machine_name: row
+                .try_get::<&str, _>("MachineName")?
+                .map(Into::into)
+                .unwrap_or_default(),
+            sheet: Sheet::try_from(row)?,
+        })
+    }
+}
+
+/// Queued program as listed for a machine
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MachineProgram {
+    pub program: String,
+    pub repeats: i32,
+    /// cutting time in seconds
+    pub cutting_time: f64,
+    /// cutting time as an ISO 8601 duration
+    pub cutting_time_iso: String,
+    /// earliest due date of the parts on the program
+    pub due_date: Option<NaiveDateTime>,
+    /// programs with a higher priority are listed first, see [`super::ProgramPriority`]
+    pub priority: i32,
+    /// every repeat is complete, but the program is still in its grace period
+    pub just_completed: bool,
+    /// hidden from the queue, see [`super::HiddenProgram`]
+    pub hidden: bool,
+}
+
+impl MachineProgram {
+    /// get the machines programs can be queued on
+    pub async fn get_machines(conn: &mut SqlConn<'_>) -> Result<Vec<String>> {
+        Ok(conn
+            .simple_query("select distinct MachineName from ProgramMachine")
+            .await?
+            .into_first_result()
+            .await?
+            .iter()
+            .map(|row| row.get::<&str, _>(0))
+            .map(|val| String::from(val.unwrap_or("")))
+            .collect())
+    }
+
+    /// query of [`Self::get_by_machine`]
+    pub const BY_MACHINE_SQL: &str = r#"
+SELECT TOP (@P2) *
+FROM (
+SELECT DISTINCT
+    ProgramMachine.ProgramName,
+    [{cutting_time}] AS CuttingTime,
+    rpt.Repeats,
+    due.DueDate,
+    ISNULL(pri.Priority, 0) AS Priority,
+    CAST(IIF(rpt.Repeats IS NULL, 1, 0) AS BIT) AS JustCompleted,
+    CAST(IIF(hid.ProgramName IS NULL, 0, 1) AS BIT) AS Hidden
+FROM ProgramMachine
+LEFT JOIN (
+    SELECT
+		ProgramName AS p,
+		COUNT(RepeatID) AS Repeats
+    FROM Program
+    WHERE NOT EXISTS (
+        SELECT 1
+        FROM TransAct
+        WHERE TransType IN ({trans_types})
+        AND TransAct.ProgramName=Program.ProgramName
+        AND TransAct.ProgramRepeat=Program.RepeatId
+    )
+    GROUP BY ProgramName
+) AS rpt
+    ON rpt.p=ProgramMachine.ProgramName
+OUTER APPLY (
+    SELECT
+        MIN(NULLIF(Part.DueDate, '1900-01-01')) AS DueDate
+    FROM PIP
+    INNER JOIN Part ON PIP.PartName=Part.PartName AND PIP.WONumber=Part.WONumber
+    WHERE PIP.ProgramName=ProgramMachine.ProgramName
+) AS due
+LEFT JOIN ProgramPriority AS pri
+    ON pri.ProgramName=ProgramMachine.ProgramName
+LEFT JOIN HiddenProgram AS hid
+    ON hid.ProgramName=ProgramMachine.ProgramName
+OUTER APPLY (
+    SELECT
+        MAX(PostedAt) AS CompletedAt
+    FROM SimTransLog
+    WHERE SimTransLog.ProgramName=ProgramMachine.ProgramName
+    AND TransType IN ({trans_types})
+) AS done
+WHERE MachineName=@P1
+AND (
+    rpt.Repeats > 0
+    OR (@P3 > 0 AND done.CompletedAt >= DATEADD(second, -@P3, SYSDATETIME()))
+)
+AND (@P4 = 1 OR hid.ProgramName IS NULL)
+) AS prg
+ORDER BY
+    Priority DESC,
+    IIF(@P5 = 1 AND DueDate IS NULL, 1, 0),
+    IIF(@P5 = 1, DueDate, NULL),
+    ProgramName
+        "#;
+
+    /// get up to `limit` programs with repeats that have not been completed for a machine
+    ///
+    /// Programs are listed by priority, highest first, then by name. With
+    /// `by_due_date`, programs of the same priority are listed by earliest due
+    /// date instead, with programs without one last. Sorting is done before
+    /// `limit` is applied, so the earliest due programs are the ones kept.
+    ///
+    /// Due dates come from `Part.DueDate` of the parts nested on each program,
+    /// where Sigmanest stores `1900-01-01` for parts without a due date.
+    ///
+    /// Programs completed within `grace`, by the time of their last completion
+    /// in `SimTransLog`, are still listed and flagged as just completed.
+    ///
+    /// Cutting time is read from the column of `settings`.
+    ///
+    /// Hidden programs are only listed if `include_hidden`.
+    pub async fn get_by_machine(
+        conn: &mut SqlConn<'_>,
+        machine: &MachineName,
+        limit: usize,
+        grace: Duration,
+        settings: &QuerySettings,
+        include_hidden: bool,
+        by_due_date: bool,
+    ) -> Result<Vec<Self>> {
+        conn.query(
+            settings.render(Self::BY_MACHINE_SQL),
+            &[
+                &machine.as_str(),
+                &(limit as i64),
+                &grace.num_seconds(),
+                &include_hidden,
+                &by_due_date,
+            ],
+        )
+        .await?
+        .into_first_result()
+        .await?
+        .iter()
+        .map(Self::try_from)
+        .collect()
+    }
+
+    /// collapse programs listed more than once, such as by join fan-out
+    ///
+    /// The first listing of a program keeps its place, with the most repeats
+    /// and longest cutting time of its duplicates. Returns the names of the
+    /// programs that had duplicates.
+    pub fn dedup_by_program(programs: &mut Vec<Self>) -> Vec<String> {
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut duplicated = Vec::new();
+
+        let mut deduped: Vec<Self> = Vec::with_capacity(programs.len());
+        for prg in programs.drain(..) {
+            let position = match positions.get(&prg.program) {
+                Some(&position) => position,
+                None => {
+                    positions.insert(prg.program.clone(), deduped.len());
+                    deduped.push(prg);
+                    continue;
+                }
+            };
+
+            let kept = &mut deduped[position];
+            kept.repeats = kept.repeats.max(prg.repeats);
+            if prg.cutting_time > kept.cutting_time {
+                kept.cutting_time = prg.cutting_time;
+                kept.cutting_time_iso = prg.cutting_time_iso;
+            }
+            if !duplicated.contains(&prg.program) {
+                duplicated.push(prg.program);
+            }
+        }
+
+        *programs = deduped;
+        duplicated
+    }
+}
+
+impl TryFrom<&tiberius::Row> for MachineProgram {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        let program: String = row
+            .try_get::<&str, _>("ProgramName")?
+            .map(Into::into)
+            .unwrap();
+
+        // bad data in one program should not fail the whole list
+        // programs in their grace period have no repeats left
+        let repeats = row.try_get("Repeats")?.unwrap_or_default();
+        let cutting_time = row.try_get("CuttingTime")?.unwrap_or_else(|| {
+            log::warn!("Program {} has no cutting time, using 0", program);
+            0.0
+        });
+
+        Ok(Self {
+            program,
+            repeats,
+            cutting_time,
+            cutting_time_iso: iso8601_duration(cutting_time),
+            due_date: row.try_get("DueDate")?,
+            priority: row.try_get("Priority")?.unwrap_or_default(),
+            just_completed: row.try_get("JustCompleted")?.unwrap_or_default(),
+            hidden: row.try_get("Hidden")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Place of a program in its machine's queue, ordered as listed for the machine
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuePosition {
+    pub program: String,
+    pub machine_name: String,
+    /// 1 for the first program in the queue
+    pub position: i32,
+    pub total: i32,
+}
+
+impl QueuePosition {
+    /// query of [`Self::get`]
+    pub const GET_SQL: &str = r#"
+WITH active AS (
+    SELECT DISTINCT
+        ProgramMachine.ProgramName, MachineName,
+        ISNULL(pri.Priority, 0) AS Priority
+    FROM ProgramMachine
+    LEFT JOIN ProgramPriority AS pri
+        ON pri.ProgramName=ProgramMachine.ProgramName
+    WHERE EXISTS (
+        SELECT 1
+        FROM Program
+        WHERE Program.ProgramName=ProgramMachine.ProgramName
+        AND NOT EXISTS (
+            SELECT 1
+            FROM TransAct
+            WHERE TransType IN ({trans_types})
+            AND TransAct.ProgramName=Program.ProgramName
+            AND TransAct.ProgramRepeat=Program.RepeatId
+        )
+    )
+)
+SELECT TOP 1
+    this.ProgramName,
+    this.MachineName,
+    (
+        SELECT COUNT(*) FROM active
+        WHERE active.MachineName=this.MachineName
+        AND (
+            active.Priority>this.Priority
+            OR (active.Priority=this.Priority AND active.ProgramName<=this.ProgramName)
+        )
+    ) AS Position,
+    (
+        SELECT COUNT(*) FROM active
+        WHERE active.MachineName=this.MachineName
+    ) AS Total
+FROM active AS this
+WHERE this.ProgramName=@P1
+        "#;
+
+    /// get the queue position of a program with repeats that have not been completed
+    pub async fn get(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        program: &str,
+    ) -> Result<Self> {
+        match conn
+            .query(settings.render(Self::GET_SQL), &[&program])
+            .await?
+            .into_row()
+            .await?
+        {
+            Some(row) => Self::try_from(&row),
+            None => Err(Error::NotFound(format!(
+                "Program {} is not in any machine queue",
+                program
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&tiberius::Row> for QueuePosition {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            program: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            machine_name: row
+                .try_get::<&str, _>("MachineName")?
+                .map(Into::into)
+                .unwrap_or_default(),
+            position: row.try_get("Position")?.unwrap_or_default(),
+            total: row.try_get("Total")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Estimate of when a queued program will start, from the programs ahead of it
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueEstimate {
+    pub program: String,
+    pub machine_name: String,
+    pub programs_ahead: i32,
+    /// cutting time of every repeat left of the programs ahead
+    pub seconds_ahead: f64,
+    pub estimated_start: NaiveDateTime,
+}
+
+impl QueueEstimate {
+    /// query of [`Self::get`]
+    pub const GET_SQL: &str = r#"
+WITH repeats AS (
+    SELECT
+        ProgramName,
+        COUNT(RepeatID) AS Repeats
+    FROM Program
+    WHERE NOT EXISTS (
+        SELECT 1
+        FROM TransAct
+        WHERE TransType IN ({trans_types})
+        AND TransAct.ProgramName=Program.ProgramName
+        AND TransAct.ProgramRepeat=Program.RepeatId
+    )
+    GROUP BY ProgramName
+),
+active AS (
+    SELECT DISTINCT
+        ProgramMachine.ProgramName, MachineName,
+        [{cutting_time}] AS CuttingTime, repeats.Repeats,
+        ISNULL(pri.Priority, 0) AS Priority
+    FROM ProgramMachine
+    INNER JOIN repeats
+        ON repeats.ProgramName=ProgramMachine.ProgramName
+    LEFT JOIN ProgramPriority AS pri
+        ON pri.ProgramName=ProgramMachine.ProgramName
+)
+SELECT TOP 1
+    this.ProgramName,
+    this.MachineName,
+    ahead.Programs AS ProgramsAhead,
+    CAST(ISNULL(ahead.Seconds, 0) AS FLOAT) AS SecondsAhead
+FROM active AS this
+OUTER APPLY (
+    SELECT
+        COUNT(*) AS Programs,
+        SUM(active.CuttingTime * active.Repeats) AS Seconds
+    FROM active
+    WHERE active.MachineName=this.MachineName
+    AND (
+        active.Priority>this.Priority
+        OR (active.Priority=this.Priority AND active.ProgramName<this.ProgramName)
+    )
+) AS ahead
+WHERE this.ProgramName=@P1
+        "#;
+
+    /// estimate when a program with repeats that have not been completed will start
+    ///
+    /// Programs ahead in the queue are the same as for [`QueuePosition`], and
+    /// each is counted for every repeat it has left. The estimate assumes the
+    /// machine starts on the queue now and runs without breaks.
+    pub async fn get(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        program: &str,
+    ) -> Result<Self> {
+        match conn
+            .query(settings.render(Self::GET_SQL), &[&program])
+            .await?
+            .into_row()
+            .await?
+        {
+            Some(row) => Self::try_from(&row),
+            None => Err(Error::NotFound(format!(
+                "Program {} is not in any machine queue",
+                program
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&tiberius::Row> for QueueEstimate {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        let seconds_ahead: f64 = row.try_get("SecondsAhead")?.unwrap_or_default();
+
+        Ok(Self {
+            program: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            machine_name: row
+                .try_get::<&str, _>("MachineName")?
+                .map(Into::into)
+                .unwrap_or_default(),
+            programs_ahead: row.try_get("ProgramsAhead")?.unwrap_or_default(),
+            seconds_ahead,
+            estimated_start: Local::now().naive_local()
+                + Duration::seconds(seconds_ahead.round() as i64),
+        })
+    }
+}
+
+/// Program sharing parts with another program
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedProgram {
+    pub program_name: String,
+    /// parts nested on both programs, with their quantity on this program
+    pub parts: Vec<SharedPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedPart {
+    pub part_name: String,
+    pub qty: i32,
+}
+
+impl RelatedProgram {
+    /// get other in process programs that have at least one part in common with a program
+    pub async fn get_by_part(conn: &mut SqlConn<'_>, program: &str) -> Result<Vec<Self>> {
+        let rows = conn
+            .query(
+                r#"
+select
+	other.ProgramName, other.PartName,
+	sum(other.QtyInProcess) as Qty
+from PIP as this
+inner join PIP as other
+	on other.PartName=this.PartName
+	and other.ProgramName<>this.ProgramName
+where this.ProgramName=@P1
+group by other.ProgramName, other.PartName
+order by other.ProgramName, other.PartName;
+        "#,
+                &[&program],
+            )
+            .await?
+            .into_first_result()
+            .await?;
+
+        // rows are ordered by program, so each program's parts are consecutive
+        let mut related: Vec<Self> = Vec::new();
+        for row in &rows {
+            let program_name: &str = row.try_get("ProgramName")?.unwrap_or_default();
+            let part = SharedPart {
+                part_name: row
+                    .try_get::<&str, _>("PartName")?
+                    .map(Into::into)
+                    .unwrap_or_default(),
+                qty: row.try_get("Qty")?.unwrap_or_default(),
+            };
+
+            match related.last_mut() {
+                Some(last) if last.program_name == program_name => last.parts.push(part),
+                _ => related.push(Self {
+                    program_name: program_name.into(),
+                    parts: vec![part],
+                }),
+            }
+        }
+
+        Ok(related)
+    }
+}
+
+/// Program with parts of a work order, and how many of its repeats are complete
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkOrderProgram {
+    pub program_name: String,
+    pub machine_name: String,
+    pub repeats: i32,
+    /// repeats with a completion posted that SimTrans has not processed yet
+    pub completed_repeats: i32,
+    pub complete: bool,
+    /// parts of the work order nested on the program, with their quantity on it
+    pub parts: Vec<SharedPart>,
+}
+
+impl WorkOrderProgram {
+    /// query of [`Self::get_by_work_order`]
+    pub const BY_WORK_ORDER_SQL: &str = r#"
+select
+	PIP.ProgramName, PIP.PartName,
+	sum(PIP.QtyInProcess) as Qty,
+	prg.MachineName, prg.Repeats, prg.CompletedRepeats
+from PIP
+cross apply (
+	select
+		max(Program.MachineName) as MachineName,
+		count(distinct Program.RepeatID) as Repeats,
+		count(distinct TransAct.ProgramRepeat) as CompletedRepeats
+	from Program
+	left join TransAct
+		on TransAct.TransType in ({trans_types})
+		and TransAct.ProgramName=Program.ProgramName
+		and TransAct.ProgramRepeat=Program.RepeatID
+	where Program.ProgramName=PIP.ProgramName
+) as prg
+where PIP.WONumber=@P1
+group by PIP.ProgramName, PIP.PartName, prg.MachineName, prg.Repeats, prg.CompletedRepeats
+order by PIP.ProgramName, PIP.PartName;
+        "#;
+
+    /// get the in process programs with parts of a work order
+    ///
+    /// Programs leave `PIP` once SimTrans has processed their completion, so
+    /// only programs that are not fully processed are listed.
+    pub async fn get_by_work_order(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        work_order: &str,
+    ) -> Result<Vec<Self>> {
+        let rows = conn
+            .query(settings.render(Self::BY_WORK_ORDER_SQL), &[&work_order])
+            .await?
+            .into_first_result()
+            .await?;
+
+        // rows are ordered by program, so each program's parts are consecutive
+        let mut programs: Vec<Self> = Vec::new();
+        for row in &rows {
+            let program_name: &str = row.try_get("ProgramName")?.unwrap_or_default();
+            let part = SharedPart {
+                part_name: row
+                    .try_get::<&str, _>("PartName")?
+                    .map(Into::into)
+                    .unwrap_or_default(),
+                qty: row.try_get("Qty")?.unwrap_or_default(),
+            };
+
+            match programs.last_mut() {
+                Some(last) if last.program_name == program_name => last.parts.push(part),
+                _ => {
+                    let repeats = row.try_get("Repeats")?.unwrap_or_default();
+                    let completed_repeats = row.try_get("CompletedRepeats")?.unwrap_or_default();
+                    programs.push(Self {
+                        program_name: program_name.into(),
+                        machine_name: row
+                            .try_get::<&str, _>("MachineName")?
+                            .map(Into::into)
+                            .unwrap_or_default(),
+                        repeats,
+                        completed_repeats,
+                        complete: repeats > 0 && completed_repeats >= repeats,
+                        parts: vec![part],
+                    })
+                }
+            }
+        }
+
+        if programs.is_empty() {
+            return Err(Error::NotFound(format!(
+                "No programs found for work order {}",
+                work_order
+            )));
+        }
+
+        Ok(programs)
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{db::SqlConn, Result};
 use serde::{Deserialize, Serialize};
 
+use super::{BoundingBox, Part};
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Remnant {
@@ -34,7 +36,7 @@ from Remnant;
                 row.try_get::<&str, _>("ProgramName")?
                     .map(Into::into)
                     .unwrap(),
-                row.try_get::<i32, _>("RepeatID")?.map(Into::into).unwrap(),
+                row.try_get::<i32, _>("RepeatID")?.unwrap(),
                 Self::try_from(row)?,
             ))
         })
@@ -83,3 +85,43 @@ impl TryFrom<&tiberius::Row> for Remnant {
         })
     }
 }
+
+/// Offcut expected from the sheet a program is nested on
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemnantEstimate {
+    pub sheet: BoundingBox,
+    /// area of the sheet taken by the nested parts
+    pub nested_area: f64,
+    /// sheet area left over, `None` if the sheet has no dimensions or the
+    /// parts take more than the whole sheet
+    pub area: Option<f64>,
+    /// remnants the program was nested with, with their dimensions
+    pub remnants: Vec<Remnant>,
+}
+
+impl RemnantEstimate {
+    /// estimate the offcut of a sheet from the parts nested on it
+    ///
+    /// Part placements are not stored in the database, so only the area of the
+    /// offcut can be computed, not its shape.
+    pub fn new(sheet: BoundingBox, parts: &[Part], remnants: Vec<Remnant>) -> Self {
+        let nested_area: f64 = parts
+            .iter()
+            .map(|part| part.nested_area * f64::from(part.part_qty))
+            .sum();
+
+        let sheet_area = sheet.width * sheet.height;
+        let area = match sheet_area > 0.0 && nested_area <= sheet_area {
+            true => Some(sheet_area - nested_area),
+            false => None,
+        };
+
+        Self {
+            sheet,
+            nested_area,
+            area,
+            remnants,
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use std::collections::HashMap;
 
-use crate::{db::SqlConn, Result};
+use crate::{db::SqlConn, Error, Result};
 use serde::{Deserialize, Serialize};
 
+use super::{Part, QuerySettings};
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Sheet {
     pub sheet_name: String,
     pub material_master: String,
+    /// sheet is a single named sheet (batch level inventory) rather than
+    /// generic stock, which is named by its material master
+    pub is_singleton: bool,
 }
 
 impl Sheet {
@@ -18,7 +23,8 @@ impl Sheet {
 select
 	ProgramName,
 	Stock.SheetName,
-	PrimeCode as MaterialMaster
+	PrimeCode as MaterialMaster,
+	cast(iif(Stock.SheetName<>PrimeCode, 1, 0) as bit) as IsSingleton
 from Stock
 inner join STPrgArc on STPrgArc.SheetName=Stock.SheetName
         "#,
@@ -39,6 +45,44 @@ inner join STPrgArc on STPrgArc.SheetName=Stock.SheetName
     }
 }
 
+impl Sheet {
+    /// query of [`Self::queued_repeats`]
+    pub const QUEUED_REPEATS_SQL: &str = r#"
+select
+	count(*) as Repeats
+from Program
+inner join Stock on Stock.SheetName=Program.SheetName
+where PrimeCode=@P1
+and not exists (
+	select 1
+	from TransAct
+	where TransType in ({trans_types})
+	and TransAct.ProgramName=Program.ProgramName
+	and TransAct.ProgramRepeat=Program.RepeatId
+)
+        "#;
+
+    /// count the repeats that have not been completed of programs nested on a material
+    ///
+    /// Both generic stock and singleton sheets of the material are counted.
+    pub async fn queued_repeats(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        material_master: &str,
+    ) -> Result<i32> {
+        Ok(conn
+            .query(
+                settings.render(Self::QUEUED_REPEATS_SQL),
+                &[&material_master],
+            )
+            .await?
+            .into_row()
+            .await?
+            .and_then(|row| row.get("Repeats"))
+            .unwrap_or_default())
+    }
+}
+
 impl TryFrom<&tiberius::Row> for Sheet {
     type Error = crate::Error;
 
@@ -52,6 +96,90 @@ impl TryFrom<&tiberius::Row> for Sheet {
                 .try_get::<&str, _>("MaterialMaster")?
                 .map(Into::into)
                 .unwrap_or_default(),
+            is_singleton: row.try_get("IsSingleton")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Overall dimensions of the sheet a program is nested on
+///
+/// Part placements are not stored in the database, so the box is the whole sheet.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoundingBox {
+    pub width: f64,
+    /// sheet length
+    pub height: f64,
+}
+
+impl BoundingBox {
+    /// get the bounding box of a program's nest
+    pub async fn get(conn: &mut SqlConn<'_>, program: &str) -> Result<Self> {
+        let row = conn
+            .query(
+                r#"
+select top 1
+	Stock.Length, Stock.Width
+from Stock
+inner join Program on Stock.SheetName=Program.SheetName
+where ProgramName=@P1;
+        "#,
+                &[&program],
+            )
+            .await?
+            .into_row()
+            .await?;
+
+        match row {
+            Some(row) => Self::try_from(&row),
+            None => Err(Error::NotFound(format!(
+                "No sheet found for program {}",
+                program
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&tiberius::Row> for BoundingBox {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            width: row.try_get("Width")?.unwrap_or_default(),
+            height: row.try_get("Length")?.unwrap_or_default(),
         })
     }
 }
+
+/// Share of a sheet taken by the parts nested on it
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Utilization {
+    /// true area of the nested parts
+    pub part_area: f64,
+    /// `None` if the sheet has no dimensions
+    pub sheet_area: Option<f64>,
+    /// part area as a percentage of the sheet area, `None` if either is unknown
+    pub utilization_pct: Option<f64>,
+}
+
+impl Utilization {
+    pub fn new(sheet: &BoundingBox, parts: &[Part]) -> Self {
+        let part_area: f64 = parts
+            .iter()
+            .map(|part| part.true_area * f64::from(part.part_qty))
+            .sum();
+        let sheet_area = Some(sheet.width * sheet.height).filter(|&area| area > 0.0);
+
+        let utilization_pct = match (sheet_area, part_area > 0.0) {
+            (Some(sheet_area), true) => Some(part_area / sheet_area * 100.0),
+            _ => None,
+        };
+
+        Self {
+            part_area,
+            sheet_area,
+            utilization_pct,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{db::SqlConn, Error, Result};
+
+/// Program completion waiting to be posted to SimTrans, while SimTrans pushes
+/// are paused or completions are buffered, see [`crate::buffer::SimTransBuffer`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingSimTrans {
+    pub program: String,
+    pub plant: String,
+    pub operator: String,
+    pub trans_type: String,
+    pub queued_at: DateTime<Utc>,
+}
+
+impl PendingSimTrans {
+    pub fn new(program: &str, plant: &str, operator: &str, trans_type: &str) -> Self {
+        Self {
+            program: program.into(),
+            plant: plant.into(),
+            operator: operator.into(),
+            trans_type: trans_type.into(),
+            queued_at: Utc::now(),
+        }
+    }
+}
+
+/// SimTrans transaction posted by the server
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostedTransaction {
+    pub trans_type: String,
+    pub program_name: String,
+    pub program_repeat: i32,
+    pub operator: Option<String>,
+    pub posted_at: NaiveDateTime,
+}
+
+impl PostedTransaction {
+    /// query of [`Self::get_range`]
+    pub const RANGE_SQL: &str = r#"
+select
+	TransType, ProgramName, ProgramRepeat, Operator, PostedAt
+from SimTransLog
+where TransType=@P1
+and PostedAt>=@P2 and PostedAt<@P3
+order by PostedAt
+        "#;
+
+    /// get transactions of a type posted in `[since, until)`
+    pub async fn get_range(
+        conn: &mut SqlConn<'_>,
+        trans_type: &str,
+        since: NaiveDateTime,
+        until: NaiveDateTime,
+    ) -> Result<Vec<Self>> {
+        conn.query(Self::RANGE_SQL, &[&trans_type, &since, &until])
+            .await?
+            .into_first_result()
+            .await?
+            .iter()
+            .map(Self::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<&tiberius::Row> for PostedTransaction {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            trans_type: row.try_get::<&str, _>("TransType")?.unwrap().into(),
+            program_name: row.try_get::<&str, _>("ProgramName")?.unwrap().into(),
+            program_repeat: row.try_get("ProgramRepeat")?.unwrap(),
+            operator: row.try_get::<&str, _>("Operator")?.map(Into::into),
+            posted_at: row.try_get("PostedAt")?.unwrap(),
+        })
+    }
+}
+
+/// post a program completion to SimTrans, logging the operator who completed it
+///
+/// Completions are usually posted as `SN70`, but `trans_type` may be any type
+/// allowed by the config, such as `SN71` for rework.
+///
+/// If `proc` is set, the completion is posted by calling that stored procedure
+/// with the program, repeat and district, rather than inserting into `TransAct`.
+/// `proc` must already be checked to be an identifier, see [`crate::config::Config`].
+///
+/// Fails with [`Error::NotFound`] if the program does not exist, as nothing is posted.
+pub async fn post_program_complete(
+    conn: &mut SqlConn<'_>,
+    program: &str,
+    district: i32,
+    operator: &str,
+    trans_type: &str,
+    proc: Option<&str>,
+) -> Result<()> {
+    if let Some(proc) = proc {
+        return post_program_complete_proc(conn, program, district, operator, trans_type, proc)
+            .await;
+    }
+
+    let result = conn
+        .execute(
+            r#"
+INSERT INTO TransAct(TransType,District,ProgramName,ProgramRepeat)
+SELECT TOP 1
+    @P4,@P2,@P1,RepeatId
+FROM Program
+WHERE ProgramName=@P1;
+INSERT INTO SimTransLog(TransType,ProgramName,ProgramRepeat,Operator)
+SELECT TOP 1
+    @P4,ProgramName,RepeatId,@P3
+FROM Program
+WHERE ProgramName=@P1;
+        "#,
+            &[&program, &district, &operator, &trans_type],
+        )
+        .await?;
+
+    inserted(result.rows_affected(), program)
+}
+
+/// check that a completion was posted, from the rows affected by each
+/// statement of [`post_program_complete`]
+///
+/// The `INSERT ... SELECT` statements insert nothing if the program does not
+/// exist, which is [`Error::NotFound`].
+fn inserted(rows_affected: &[u64], program: &str) -> Result<()> {
+    // the log insert matches the same program, so only the first count is checked
+    let inserted = rows_affected.first().is_some_and(|&rows| rows > 0);
+    found(inserted.then_some(()), program)
+}
+
+/// get the repeat completions of a program are posted for
+///
+/// Fails with [`Error::NotFound`] if the program does not exist, so a
+/// completion can be checked before anything about it is recorded.
+pub async fn completion_repeat(conn: &mut SqlConn<'_>, program: &str) -> Result<i32> {
+    let repeat = match conn
+        .query(
+            "SELECT TOP 1 RepeatId FROM Program WHERE ProgramName=@P1",
+            &[&program],
+        )
+        .await?
+        .into_row()
+        .await?
+    {
+        Some(row) => row.try_get("RepeatId")?,
+        None => None,
+    };
+
+    found(repeat, program)
+}
+
+/// what matched the program of a completion, or [`Error::NotFound`] if nothing did
+fn found<T>(matched: Option<T>, program: &str) -> Result<T> {
+    matched.ok_or_else(|| Error::NotFound(format!("Program {} not found for completion", program)))
+}
+
+/// Most completions posted in one statement by [`post_program_completes`]
+///
+/// Each completion takes 3 parameters, and SQL Server allows at most 2100.
+pub const MAX_BATCHED_COMPLETIONS: usize = 500;
+
+/// post several program completions to SimTrans in one transaction, see [`post_program_complete`]
+///
+/// Completions of programs that do not exist are skipped, rather than failing
+/// the others. Returns the number of completions posted. At most
+/// [`MAX_BATCHED_COMPLETIONS`] can be posted at once, more fail with
+/// [`Error::BadRequest`].
+pub async fn post_program_completes(
+    conn: &mut SqlConn<'_>,
+    completions: &[PendingSimTrans],
+    district: i32,
+) -> Result<usize> {
+    if completions.len() > MAX_BATCHED_COMPLETIONS {
+        return Err(Error::BadRequest(format!(
+            "At most {} completions can be posted at once, got {}",
+            MAX_BATCHED_COMPLETIONS,
+            completions.len()
+        )));
+    }
+
+    // @P1 is the district, then the program, type and operator of each completion
+    let values = (0..completions.len())
+        .map(|i| format!("(@P{},@P{},@P{})", 3 * i + 2, 3 * i + 3, 3 * i + 4))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut params: Vec<&dyn tiberius::ToSql> = vec![&district];
+    for completion in completions {
+        params.push(&completion.program);
+        params.push(&completion.trans_type);
+        params.push(&completion.operator);
+    }
+
+    let result = conn
+        .execute(
+            format!(
+                r#"
+SET XACT_ABORT ON;
+BEGIN TRANSACTION;
+INSERT INTO TransAct(TransType,District,ProgramName,ProgramRepeat)
+SELECT
+    c.TransType,@P1,c.ProgramName,prg.RepeatId
+FROM (VALUES {values}) AS c(ProgramName,TransType,Operator)
+CROSS APPLY (SELECT TOP 1 RepeatId FROM Program WHERE ProgramName=c.ProgramName) AS prg;
+INSERT INTO SimTransLog(TransType,ProgramName,ProgramRepeat,Operator)
+SELECT
+    c.TransType,c.ProgramName,prg.RepeatId,c.Operator
+FROM (VALUES {values}) AS c(ProgramName,TransType,Operator)
+CROSS APPLY (SELECT TOP 1 RepeatId FROM Program WHERE ProgramName=c.ProgramName) AS prg;
+COMMIT TRANSACTION;
+        "#
+            ),
+            &params,
+        )
+        .await?;
+
+    // both inserts add a row for each posted completion
+    Ok(result.total() as usize / 2)
+}
+
+/// post a program completion through a stored procedure, see [`post_program_complete`]
+async fn post_program_complete_proc(
+    conn: &mut SqlConn<'_>,
+    program: &str,
+    district: i32,
+    operator: &str,
+    trans_type: &str,
+    proc: &str,
+) -> Result<()> {
+    let repeat = completion_repeat(conn, program).await?;
+
+    conn.execute(
+        format!(
+            r#"
+EXEC {} @P1, @P2, @P3;
+INSERT INTO SimTransLog(TransType,ProgramName,ProgramRepeat,Operator)
+VALUES (@P4,@P1,@P2,@P5);
+        "#,
+            proc
+        ),
+        &[&program, &repeat, &district, &trans_type, &operator],
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_without_matching_program_is_not_found() {
+        match found::<i32>(None, "1200X-01") {
+            Err(Error::NotFound(detail)) => {
+                assert_eq!(detail, "Program 1200X-01 not found for completion")
+            }
+            other => panic!("expected not found, got {:?}", other),
+        }
+        assert_eq!(found(Some(2), "1200X-01").unwrap(), 2);
+    }
+
+    #[test]
+    fn completion_inserting_nothing_is_not_found() {
+        for rows_affected in [&[0, 0][..], &[]] {
+            match inserted(rows_affected, "1200X-01") {
+                Err(e @ Error::NotFound(_)) => {
+                    assert_eq!(e.status(), axum::http::StatusCode::NOT_FOUND)
+                }
+                other => panic!("expected not found, got {:?}", other),
+            }
+        }
+        assert!(inserted(&[1, 1], "1200X-01").is_ok());
+    }
+}
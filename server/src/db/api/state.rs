@@ -0,0 +1,317 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::QuerySettings;
+use crate::{db::SqlConn, Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProgramState {
+    Initiated,
+    Processing,
+    Complete,
+    Cancelled,
+}
+
+impl ProgramState {
+    /// all program states, in transition order
+    pub const ALL: [ProgramState; 4] = [
+        ProgramState::Initiated,
+        ProgramState::Processing,
+        ProgramState::Complete,
+        ProgramState::Cancelled,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProgramState::Initiated => "Initiated",
+            ProgramState::Processing => "Processing",
+            ProgramState::Complete => "Complete",
+            ProgramState::Cancelled => "Cancelled",
+        }
+    }
+
+    /// program in this state may be moved to `to`
+    ///
+    /// Re-posting the current state is allowed so its batch can be changed,
+    /// except for `Complete`, as that would post the completion to SimTrans
+    /// again. Completed programs cannot be moved out of `Complete`.
+    pub fn can_transition_to(&self, to: ProgramState) -> bool {
+        use ProgramState::*;
+
+        (*self == to && to != Complete)
+            || matches!(
+                (self, to),
+                (Initiated, Processing | Cancelled)
+                    | (Processing, Initiated | Complete | Cancelled)
+                    | (Cancelled, Initiated)
+            )
+    }
+}
+
+impl std::str::FromStr for ProgramState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Initiated" => Ok(ProgramState::Initiated),
+            "Processing" => Ok(ProgramState::Processing),
+            "Complete" => Ok(ProgramState::Complete),
+            "Cancelled" => Ok(ProgramState::Cancelled),
+            _ => Err(Error::BadRequest(format!("Unknown program state `{}`", s))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateLogEntry {
+    pub program_name: String,
+    pub batch: Option<String>,
+    pub state: ProgramState,
+    pub operator: Option<String>,
+    pub reason: Option<String>,
+    pub logged_at: NaiveDateTime,
+}
+
+impl StateLogEntry {
+    /// record a program state transition
+    pub async fn record(
+        conn: &mut SqlConn<'_>,
+        program: &str,
+        batch: Option<&str>,
+        state: ProgramState,
+        operator: &str,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        conn.execute(
+            r#"
+insert into ProgramStateLog(ProgramName, Batch, State, Operator, Reason)
+values (@P1, @P2, @P3, @P4, @P5);
+        "#,
+            &[&program, &batch, &state.as_str(), &operator, &reason],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// query of [`Self::latest`]
+    pub const LATEST_SQL: &str = r#"
+select top 1
+	ProgramName, Batch, State, Operator, Reason, LoggedAt
+from ProgramStateLog
+where ProgramName=@P1
+order by LoggedAt desc, Id desc;
+        "#;
+
+    /// get the most recent state transition of a program
+    pub async fn latest(conn: &mut SqlConn<'_>, program: &str) -> Result<Option<Self>> {
+        conn.query(Self::LATEST_SQL, &[&program])
+            .await?
+            .into_row()
+            .await?
+            .as_ref()
+            .map(Self::try_from)
+            .transpose()
+    }
+
+    /// query of [`Self::all_in_state`]
+    pub const IN_STATE_SQL: &str = r#"
+select
+	ProgramName, Batch, State, Operator, Reason, LoggedAt
+from (
+	select
+		ProgramName, Batch, State, Operator, Reason, LoggedAt,
+		row_number() over (partition by ProgramName order by LoggedAt desc, Id desc) as Recency
+	from ProgramStateLog
+) as latest
+where Recency=1 and State=@P1
+order by LoggedAt;
+        "#;
+
+    /// get the latest transition of every program currently in a state
+    pub async fn all_in_state(conn: &mut SqlConn<'_>, state: ProgramState) -> Result<Vec<Self>> {
+        conn.query(Self::IN_STATE_SQL, &[&state.as_str()])
+            .await?
+            .into_first_result()
+            .await?
+            .iter()
+            .map(Self::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<&tiberius::Row> for StateLogEntry {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        Ok(Self {
+            program_name: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            batch: row.try_get::<&str, _>("Batch")?.map(Into::into),
+            state: row
+                .try_get::<&str, _>("State")?
+                .unwrap_or_default()
+                .parse()?,
+            operator: row.try_get::<&str, _>("Operator")?.map(Into::into),
+            reason: row.try_get::<&str, _>("Reason")?.map(Into::into),
+            logged_at: row.try_get("LoggedAt")?.unwrap(),
+        })
+    }
+}
+
+/// How a program's place in the queue changed between two times
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueChangeKind {
+    /// queued at the end, but not at the start
+    Added,
+    /// queued at the start, but not at the end
+    Removed,
+    /// queued at both, in a different state
+    Changed,
+    /// queued at neither, in a different state, e.g. completed then archived
+    Unqueued,
+}
+
+/// Program whose state changed between two times, derived from the state log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueChange {
+    pub program_name: String,
+    pub change: QueueChangeKind,
+    /// state at the start, if the program had been logged by then
+    pub from_state: Option<ProgramState>,
+    pub to_state: ProgramState,
+}
+
+impl QueueChange {
+    /// query of [`Self::between`]
+    pub const BETWEEN_SQL: &str = r#"
+select
+	ProgramName, FromState, ToState
+from (
+	select
+		logged.ProgramName,
+		(
+			select top 1 State from ProgramStateLog
+			where ProgramName=logged.ProgramName and LoggedAt<=@P1
+			order by LoggedAt desc, Id desc
+		) as FromState,
+		(
+			select top 1 State from ProgramStateLog
+			where ProgramName=logged.ProgramName and LoggedAt<=@P2
+			order by LoggedAt desc, Id desc
+		) as ToState
+	from (
+		select distinct ProgramName from ProgramStateLog
+		where LoggedAt>@P1 and LoggedAt<=@P2
+	) as logged
+) as states
+where FromState is null or FromState<>ToState
+order by ProgramName;
+        "#;
+
+    /// get the programs whose state changed after `from`, up to and including `to`
+    ///
+    /// Programs are queued while they are `Initiated` or `Processing`.
+    pub async fn between(
+        conn: &mut SqlConn<'_>,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Self>> {
+        conn.query(Self::BETWEEN_SQL, &[&from, &to])
+            .await?
+            .into_first_result()
+            .await?
+            .iter()
+            .map(Self::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<&tiberius::Row> for QueueChange {
+    type Error = crate::Error;
+
+    fn try_from(row: &tiberius::Row) -> Result<Self> {
+        let is_queued = |state: ProgramState| {
+            matches!(state, ProgramState::Initiated | ProgramState::Processing)
+        };
+
+        let from_state: Option<ProgramState> = row
+            .try_get::<&str, _>("FromState")?
+            .map(str::parse)
+            .transpose()?;
+        let to_state: ProgramState = row
+            .try_get::<&str, _>("ToState")?
+            .unwrap_or_default()
+            .parse()?;
+
+        let change = match (from_state.is_some_and(is_queued), is_queued(to_state)) {
+            (false, true) => QueueChangeKind::Added,
+            (true, false) => QueueChangeKind::Removed,
+            (true, true) => QueueChangeKind::Changed,
+            (false, false) => QueueChangeKind::Unqueued,
+        };
+
+        Ok(Self {
+            program_name: row
+                .try_get::<&str, _>("ProgramName")?
+                .map(Into::into)
+                .unwrap(),
+            change,
+            from_state,
+            to_state,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramStatus {
+    pub program: String,
+    pub current_state: Option<ProgramState>,
+    pub last_transition_at: Option<NaiveDateTime>,
+    pub simtrans_posted: bool,
+    pub batch: Option<String>,
+}
+
+impl ProgramStatus {
+    /// query of [`Self::get`]
+    pub const GET_SQL: &str = r#"
+select
+	(select count(*) from Program where ProgramName=@P1) as Programs,
+	(select count(*) from TransAct where TransType in ({trans_types}) and ProgramName=@P1) as Posted;
+        "#;
+
+    /// get the live status of a program from the state log and SimTrans
+    pub async fn get(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        program: &str,
+    ) -> Result<Self> {
+        let row = conn
+            .query(settings.render(Self::GET_SQL), &[&program])
+            .await?
+            .into_row()
+            .await?
+            .unwrap();
+
+        if row.try_get::<i32, _>("Programs")?.unwrap_or_default() == 0 {
+            return Err(Error::NotFound(format!("Program {} not found", program)));
+        }
+        let simtrans_posted = row.try_get::<i32, _>("Posted")?.unwrap_or_default() > 0;
+
+        let latest = StateLogEntry::latest(conn, program).await?;
+
+        Ok(Self {
+            program: program.into(),
+            current_state: latest.as_ref().map(|entry| entry.state),
+            last_transition_at: latest.as_ref().map(|entry| entry.logged_at),
+            simtrans_posted,
+            batch: latest.and_then(|entry| entry.batch),
+        })
+    }
+}
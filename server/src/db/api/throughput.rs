@@ -0,0 +1,93 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use super::QuerySettings;
+use crate::{db::SqlConn, Result};
+
+/// Programs a machine completed on one day, and their cutting time
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThroughputDay {
+    pub day: NaiveDate,
+    pub programs: i32,
+    pub cutting_hours: f64,
+}
+
+/// Daily completed cutting time of a machine
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MachineThroughput {
+    pub machine: String,
+    /// days with completions, in order
+    pub days: Vec<ThroughputDay>,
+}
+
+impl MachineThroughput {
+    /// query of [`Self::get_range`]
+    pub const RANGE_SQL: &str = r#"
+select
+	prg.MachineName,
+	cast(log.PostedAt as date) as Day,
+	count(*) as Programs,
+	sum(prg.CuttingTime) / 3600.0 as CuttingHours
+from SimTransLog as log
+cross apply (
+	select top 1 MachineName, CuttingTime
+	from (
+		select MachineName, [{cutting_time}] as CuttingTime from Program
+		where ProgramName=log.ProgramName and RepeatID=log.ProgramRepeat
+		union all
+		select MachineName, [{cutting_time}] as CuttingTime from STPrgArc
+		where ProgramName=log.ProgramName and RepeatID=log.ProgramRepeat
+	) as programs
+) as prg
+where log.TransType in ({trans_types})
+and log.PostedAt>=@P1 and log.PostedAt<@P2
+and (@P3 is null or prg.MachineName=@P3)
+group by prg.MachineName, cast(log.PostedAt as date)
+order by prg.MachineName, Day;
+        "#;
+
+    /// get the cutting time of programs completed in `[since, until)`, per machine and day
+    ///
+    /// Completions are taken from `SimTransLog`, as `TransAct` rows are removed
+    /// once SimTrans processes them. Cutting time is of the program, or of its
+    /// archive once SimTrans has archived it.
+    pub async fn get_range(
+        conn: &mut SqlConn<'_>,
+        settings: &QuerySettings,
+        machine: Option<&str>,
+        since: NaiveDateTime,
+        until: NaiveDateTime,
+    ) -> Result<Vec<Self>> {
+        let rows = conn
+            .query(
+                settings.render(Self::RANGE_SQL),
+                &[&since, &until, &machine],
+            )
+            .await?
+            .into_first_result()
+            .await?;
+
+        let mut machines: Vec<Self> = Vec::new();
+        for row in &rows {
+            let machine: &str = row.try_get("MachineName")?.unwrap_or_default();
+            let day = ThroughputDay {
+                day: row.try_get("Day")?.unwrap(),
+                programs: row.try_get("Programs")?.unwrap_or_default(),
+                cutting_hours: row.try_get("CuttingHours")?.unwrap_or_default(),
+            };
+
+            // rows are ordered by machine, so each machine's days are together
+            match machines.last_mut() {
+                Some(last) if last.machine == machine => last.days.push(day),
+                _ => machines.push(Self {
+                    machine: machine.into(),
+                    days: vec![day],
+                }),
+            }
+        }
+
+        Ok(machines)
+    }
+}
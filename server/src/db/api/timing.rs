@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{db::SqlConn, Error, Result};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramTiming {
+    pub planned_seconds: f64,
+    pub actual_seconds: Option<f64>,
+    pub variance_pct: Option<f64>,
+}
+
+impl ProgramTiming {
+    /// query of [`Self::get`]
+    pub const GET_SQL: &str = r#"
+select top 1
+	CuttingTime
+from Program
+where ProgramName=@P1;
+select top 1
+	cast(datediff(second, started.LoggedAt, done.LoggedAt) as float) as ActualSeconds
+from ProgramStateLog as done
+cross apply (
+	select top 1 LoggedAt
+	from ProgramStateLog
+	where ProgramName=done.ProgramName
+	and State='Processing'
+	and LoggedAt<=done.LoggedAt
+	order by LoggedAt desc
+) as started
+where done.ProgramName=@P1 and done.State='Complete'
+order by done.LoggedAt desc;
+        "#;
+
+    /// get planned cutting time of a program and the actual time it took
+    ///
+    /// Actual time is measured from the state log as the time between the
+    /// latest `Complete` transition and the `Processing` transition before it.
+    pub async fn get(conn: &mut SqlConn<'_>, program: &str) -> Result<Self> {
+        let mut results = conn
+            .query(Self::GET_SQL, &[&program])
+            .await?
+            .into_results()
+            .await?
+            .into_iter();
+
+        let planned_seconds = match results.next().and_then(|mut rows| rows.pop()) {
+            Some(row) => row.try_get("CuttingTime")?.unwrap_or_default(),
+            None => return Err(Error::NotFound(format!("Program {} not found", program))),
+        };
+
+        let actual_seconds = match results.next().and_then(|mut rows| rows.pop()) {
+            Some(row) => row.try_get("ActualSeconds")?,
+            None => None,
+        };
+
+        let variance_pct = actual_seconds
+            .filter(|_| planned_seconds > 0.0)
+            .map(|actual| (actual - planned_seconds) / planned_seconds * 100.0);
+
+        Ok(Self {
+            planned_seconds,
+            actual_seconds,
+            variance_pct,
+        })
+    }
+}
+
+/// format seconds as an ISO 8601 duration, such as `PT1H23M`
+///
+/// Seconds are rounded to whole seconds, and zero parts are left out.
+pub fn iso8601_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+
+    let mut duration = String::from("PT");
+    if hours > 0 {
+        duration.push_str(&format!("{}H", hours));
+    }
+    if minutes > 0 {
+        duration.push_str(&format!("{}M", minutes));
+    }
+    if seconds > 0 || total == 0 {
+        duration.push_str(&format!("{}S", seconds));
+    }
+
+    duration
+}
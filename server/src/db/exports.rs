@@ -2,10 +2,16 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot};
 
 use super::{
-    api::{FeedbackEntry, Nest, Part, Remnant, TransactionType},
-    DbPool,
+    api::{FeedbackEntry, Nest, Part, Remnant, Resolution, TransactionType},
+    DbPool, SqlConn,
 };
-use crate::Result;
+use crate::{machine::MachineName, Error, Result};
+
+/// Page size used if a cursor is given without a limit
+pub const DEFAULT_FEEDBACK_PAGE: usize = 100;
+
+/// Largest page of feedback that can be requested
+pub const MAX_FEEDBACK_PAGE: usize = 500;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -19,25 +25,180 @@ enum GetProgramData {
     GetParts(i32, String, oneshot::Sender<Result<Vec<Part>>>),
     GetRemnants(String, i32, oneshot::Sender<Result<Vec<Remnant>>>),
 }
-pub async fn export_feedback(db: DbPool) -> Result<Vec<FeedbackEntry<Nest>>> {
-    let mut programs: Vec<FeedbackEntry<Nest>> = db
-        .get()
-        .await?
-        .simple_query(
-            r#"
-select
+
+/// Filters and paging of a feedback export
+#[derive(Debug, Default)]
+pub struct FeedbackQuery {
+    /// only programs for these machines, if any are given
+    pub machines: Vec<MachineName>,
+    /// only entries with this resolution
+    pub resolution: Option<Resolution>,
+    /// only entries after this cursor
+    pub after: Option<FeedbackCursor>,
+    /// only entries with an `archivePacketId` greater than this
+    ///
+    /// Unlike the opaque cursor, this is the id of the entries themselves,
+    /// so a sync can persist the last id it processed and resume after it.
+    pub from_id: Option<i32>,
+    /// most entries to return
+    pub limit: Option<usize>,
+}
+
+impl FeedbackQuery {
+    /// export is paged, rather than a full dump
+    pub fn is_paged(&self) -> bool {
+        self.after.is_some() || self.limit.is_some()
+    }
+
+    fn page_size(&self) -> Option<usize> {
+        match self.is_paged() {
+            true => Some(
+                self.limit
+                    .unwrap_or(DEFAULT_FEEDBACK_PAGE)
+                    .min(MAX_FEEDBACK_PAGE),
+            ),
+            false => None,
+        }
+    }
+}
+
+/// Opaque position in the feedback export, after the entry it was made from
+///
+/// Feedback is paged in `ArchivePacketID` order, which only increases as
+/// entries are added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedbackCursor(i32);
+
+impl FeedbackCursor {
+    pub fn encode(&self) -> String {
+        format!("{:08x}", self.0)
+    }
+}
+
+impl std::str::FromStr for FeedbackCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        i32::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(|_| Error::BadRequest(format!("Invalid feedback cursor `{}`", s)))
+    }
+}
+
+/// Page of a feedback export
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedbackPage {
+    pub entries: Vec<FeedbackEntry<Nest>>,
+    /// cursor of the next page, if this page was full
+    pub next_cursor: Option<String>,
+}
+
+/// export a page of feedback
+pub async fn export_feedback_page(
+    conn: &mut SqlConn<'_>,
+    db: DbPool,
+    filter: &FeedbackQuery,
+) -> Result<FeedbackPage> {
+    let entries = export_feedback(conn, db, filter).await?;
+
+    let next_cursor = match filter.page_size() {
+        Some(size) if entries.len() == size => entries
+            .last()
+            .map(|entry| FeedbackCursor(entry.archive_packet_id).encode()),
+        _ => None,
+    };
+
+    Ok(FeedbackPage {
+        entries,
+        next_cursor,
+    })
+}
+
+/// export feedback matching a query, in `ArchivePacketID` order
+///
+/// Programs are queried on `conn`, and their parts and remnants on other
+/// connections of `db`.
+pub async fn export_feedback(
+    conn: &mut SqlConn<'_>,
+    db: DbPool,
+    filter: &FeedbackQuery,
+) -> Result<Vec<FeedbackEntry<Nest>>> {
+    let mut params = 0;
+    let mut next_param = || {
+        params += 1;
+        format!("@P{}", params)
+    };
+
+    let mut filters = Vec::new();
+    if !filter.machines.is_empty() {
+        filters.push(format!(
+            "MachineName in ({})",
+            filter
+                .machines
+                .iter()
+                .map(|_| next_param())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if filter.resolution.is_some() {
+        filters.push(format!(
+            "isnull(FeedbackResolution.Status, 'open')={}",
+            next_param()
+        ));
+    }
+    if filter.after.is_some() {
+        filters.push(format!("STPrgArc.ArchivePacketID>{}", next_param()));
+    }
+    if filter.from_id.is_some() {
+        filters.push(format!("STPrgArc.ArchivePacketID>{}", next_param()));
+    }
+    let where_clause = match filters.is_empty() {
+        true => String::new(),
+        false => format!("where {}", filters.join(" and ")),
+    };
+    let top = match filter.page_size() {
+        Some(size) => format!("top ({})", size),
+        None => String::new(),
+    };
+
+    let mut query = tiberius::Query::new(format!(
+        r#"
+select {}
 	ProgramName,
     RepeatID,
-	ArchivePacketID,
+	STPrgArc.ArchivePacketID,
     TransType,
 	MachineName,
     CuttingTime,
     Stock.SheetName,
-    PrimeCode as MaterialMaster
+    PrimeCode as MaterialMaster,
+    cast(iif(Stock.SheetName<>PrimeCode, 1, 0) as bit) as IsSingleton,
+    FeedbackResolution.Status as Resolution
 from STPrgArc
-inner join Stock on Stock.SheetName=STPrgArc.SheetName;
+inner join Stock on Stock.SheetName=STPrgArc.SheetName
+left join FeedbackResolution on FeedbackResolution.ArchivePacketID=STPrgArc.ArchivePacketID
+{}
+order by STPrgArc.ArchivePacketID;
         "#,
-        )
+        top, where_clause
+    ));
+    for machine in &filter.machines {
+        query.bind(machine.as_str());
+    }
+    if let Some(resolution) = filter.resolution {
+        query.bind(resolution.as_str());
+    }
+    if let Some(after) = filter.after {
+        query.bind(after.0);
+    }
+    if let Some(from_id) = filter.from_id {
+        query.bind(from_id);
+    }
+
+    let mut programs: Vec<FeedbackEntry<Nest>> = query
+        .query(conn)
         .await?
         .into_first_result()
         .await?
@@ -105,5 +266,62 @@ inner join Stock on Stock.SheetName=STPrgArc.SheetName;
         nests.push(program);
     }
 
+    // programs were popped from the end, so restore `ArchivePacketID` order
+    nests.reverse();
+
     Ok(nests)
 }
+
+/// Feedback entries of a part, for finding the parts with the most feedback
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartFeedbackCount {
+    pub part_name: String,
+    /// feedback type, if counts are split by type
+    pub trans_type: Option<String>,
+    /// feedback entries with the part
+    pub entries: i32,
+    /// quantity of the part over those entries
+    pub qty: i32,
+}
+
+/// count feedback by part, optionally split by feedback type, most entries first
+pub async fn export_feedback_by_part(
+    conn: &mut SqlConn<'_>,
+    by_type: bool,
+) -> Result<Vec<PartFeedbackCount>> {
+    let (trans_type, group_by) = match by_type {
+        true => ("TransType", ", TransType"),
+        false => ("cast(null as varchar(8)) as TransType", ""),
+    };
+
+    conn.simple_query(format!(
+        r#"
+select
+	PartName,
+	{},
+	count(distinct ArchivePacketID) as Entries,
+	sum(QtyInProcess) as Qty
+from STPIPArc
+group by PartName{}
+order by Entries desc, PartName;
+        "#,
+        trans_type, group_by
+    ))
+    .await?
+    .into_first_result()
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(PartFeedbackCount {
+            part_name: row
+                .try_get::<&str, _>("PartName")?
+                .map(Into::into)
+                .unwrap_or_default(),
+            trans_type: row.try_get::<&str, _>("TransType")?.map(Into::into),
+            entries: row.try_get("Entries")?.unwrap_or_default(),
+            qty: row.try_get("Qty")?.unwrap_or_default(),
+        })
+    })
+    .collect()
+}
@@ -0,0 +1,136 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::async_trait;
+use rand::Rng;
+
+/// Share of a connection's lifetime that is taken off at random
+const LIFETIME_JITTER: f64 = 0.1;
+
+/// Connection of a pool, used as the [`tiberius::Client`] it holds
+///
+/// Connections are closed once they have lived for the max lifetime, see
+/// [`conn_max_lifetime`](super::pool::conn_max_lifetime), less up to a tenth
+/// at random, so the connections a pool makes at once are not all replaced
+/// at once either.
+pub struct Connection {
+    client: bb8_tiberius::rt::Client,
+    broken: Breaker,
+    expires_at: Option<Instant>,
+}
+
+impl Connection {
+    /// breaker of the connection, to close it if a query on it times out
+    pub fn breaker(&self) -> Breaker {
+        self.broken.clone()
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+}
+
+/// `lifetime` less up to [`LIFETIME_JITTER`] of it, at random
+fn jittered(lifetime: Duration) -> Duration {
+    lifetime.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..LIFETIME_JITTER))
+}
+
+/// Marks a connection broken if a query was dropped before its response was
+/// read, so it is closed rather than returned to its pool, see [`timed`](super::timed)
+#[derive(Debug, Clone, Default)]
+pub struct Breaker(Arc<AtomicBool>);
+
+impl Breaker {
+    pub fn trip(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn is_tripped(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl Deref for Connection {
+    type Target = bb8_tiberius::rt::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+/// Makes the connections of a pool, see [`bb8::ManageConnection`]
+///
+/// Connects as [`bb8_tiberius::ConnectionManager`] does, but with Azure AD
+/// authentication gets a token for each new connection, see
+/// [`aad_token`](super::aad::aad_token). Tokens expire after about an hour,
+/// while pools make new connections for as long as the server runs.
+pub struct ConnectionManager {
+    config: tiberius::Config,
+    /// authenticate with an Azure AD token instead of the config's method
+    aad: bool,
+    inner: bb8_tiberius::ConnectionManager,
+}
+
+impl ConnectionManager {
+    pub fn new(config: tiberius::Config, aad: bool) -> Self {
+        Self {
+            inner: bb8_tiberius::ConnectionManager::new(config.clone()),
+            config,
+            aad,
+        }
+    }
+}
+
+#[async_trait]
+impl bb8::ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = bb8_tiberius::Error;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        let client = match self.aad {
+            true => {
+                let mut config = self.config.clone();
+                config.authentication(tiberius::AuthMethod::aad_token(
+                    super::aad::aad_token().await?,
+                ));
+                bb8_tiberius::ConnectionManager::new(config)
+                    .connect()
+                    .await?
+            }
+            false => self.inner.connect().await?,
+        };
+
+        Ok(Connection {
+            client,
+            broken: Breaker::default(),
+            expires_at: super::pool::conn_max_lifetime()
+                .map(|lifetime| Instant::now() + jittered(lifetime)),
+        })
+    }
+
+    async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        // checked out connections are replaced once expired
+        if conn.is_expired() {
+            return Err(std::io::Error::other("connection reached its max lifetime").into());
+        }
+
+        self.inner.is_valid(&mut conn.client).await
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.broken.is_tripped() || conn.is_expired() || self.inner.has_broken(&mut conn.client)
+    }
+}
@@ -1,5 +1,9 @@
+mod aad;
+mod manager;
 mod pool;
+pub use manager::{Breaker, Connection, ConnectionManager};
 pub use pool::*;
 
 pub mod api;
 pub mod exports;
+pub mod schema;
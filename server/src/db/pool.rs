@@ -1,29 +1,428 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
 use bb8::PooledConnection;
-use bb8_tiberius::ConnectionManager;
+
+use super::{Breaker, ConnectionManager};
+use crate::{Error, Result};
 
 /// Convenience export of database Pool type
-pub type DbPool = bb8::Pool<bb8_tiberius::ConnectionManager>;
+pub type DbPool = bb8::Pool<ConnectionManager>;
 pub type SqlConn<'a> = PooledConnection<'a, ConnectionManager>;
 
-/// Builds a connection pool for a database
-pub async fn build_db_pool() -> DbPool {
-    log::trace!("** init db pool");
+/// Header clients select the plant of a request with
+pub const PLANT_HEADER: &str = "X-Plant";
+
+/// Name of the only plant if env `SN_PLANTS` is not set
+pub const DEFAULT_PLANT: &str = "default";
+
+/// Development database, used if env `SN_PLANTS` is not set
+const DEV_HOST: &str = "HIISQLSERV6";
+const DEV_DATABASE: &str = "SNDBaseISap";
+
+/// Maximum number of connections held by a plant's pool, unless configured
+pub const POOL_MAX_SIZE: u32 = 8;
+
+/// Maximum number of connections of a plant's pool, from env `SNDB_POOL_MAX_SIZE{suffix}`
+///
+/// Each plant's pool is bounded on its own, so a busy plant cannot take the
+/// connections of another.
+fn pool_max_size(suffix: &str) -> u32 {
+    let key = format!("SNDB_POOL_MAX_SIZE{}", suffix);
+    let size = match std::env::var(&key) {
+        Ok(size) => size,
+        Err(_) => return POOL_MAX_SIZE,
+    };
+
+    match size.parse() {
+        Ok(size) if size > 0 => size,
+        _ => {
+            log::warn!(
+                "Ignoring invalid {} `{}`, using {} connections",
+                key,
+                size,
+                POOL_MAX_SIZE
+            );
+            POOL_MAX_SIZE
+        }
+    }
+}
+
+/// Connection lifetime used if `SNDB_CONN_MAX_LIFETIME_SECS` is not set
+const DEFAULT_CONN_MAX_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+/// Interval between health checks of read replicas
+pub const REPLICA_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time a read replica has to answer a health check
+const REPLICA_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Query time limit used if `SNDB_QUERY_TIMEOUT_SECS` is not set
+const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time limit for a database query, from env `SNDB_QUERY_TIMEOUT_SECS`
+pub fn query_timeout() -> Duration {
+    static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+    *TIMEOUT.get_or_init(|| {
+        std::env::var("SNDB_QUERY_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_QUERY_TIMEOUT)
+    })
+}
+
+/// Longest a pooled connection is kept, from env `SNDB_CONN_MAX_LIFETIME_SECS`
+///
+/// Each connection's lifetime is cut short at random, see
+/// [`Connection`](super::Connection), so connections made together do not
+/// expire together. A lifetime of `0` keeps connections until they break.
+pub(super) fn conn_max_lifetime() -> Option<Duration> {
+    static LIFETIME: OnceLock<Option<Duration>> = OnceLock::new();
+
+    *LIFETIME.get_or_init(|| {
+        let lifetime = std::env::var("SNDB_CONN_MAX_LIFETIME_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CONN_MAX_LIFETIME);
+
+        Some(lifetime).filter(|lifetime| !lifetime.is_zero())
+    })
+}
+
+/// run a query, failing if it does not complete within the query timeout
+///
+/// On timeout the query is dropped before its response is read, so its
+/// connection, given by its `breaker`, is closed instead of being returned
+/// to its pool.
+pub async fn timed<F, T>(breaker: Breaker, query: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match tokio::time::timeout(query_timeout(), query).await {
+        Ok(result) => result,
+        Err(_) => {
+            log::error!("Database query timed out after {:?}", query_timeout());
+            breaker.trip();
+            Err(Error::QueryTimeout)
+        }
+    }
+}
+
+/// Database authentication selected by env `SNDB_AUTH`
+///
+/// - `sql` (default): SQL authentication with `SNDB_USER` and `SNDB_PWD`
+/// - `integrated`: Windows/Kerberos authentication of the server's user
+/// - `aad`: Azure AD token, see [`aad_token`](super::aad::aad_token)
+async fn auth_method() -> tiberius::AuthMethod {
+    let method = std::env::var("SNDB_AUTH").unwrap_or_else(|_| "sql".into());
+
+    match method.to_lowercase().as_str() {
+        "sql" => {
+            log::debug!("using sql authentication");
+            let user = std::env::var("SNDB_USER").unwrap();
+            let pass = std::env::var("SNDB_PWD").unwrap();
+            tiberius::AuthMethod::sql_server(user, pass)
+        }
+        "integrated" => {
+            log::debug!("using integrated authentication");
+            tiberius::AuthMethod::Integrated
+        }
+        "aad" => {
+            log::debug!("using AAD token authentication");
+            match super::aad::aad_token().await {
+                Ok(token) => tiberius::AuthMethod::aad_token(token),
+                Err(e) => panic!("failed to acquire AAD token: {}", e),
+            }
+        }
+        other => panic!("unknown SNDB_AUTH method `{}`", other),
+    }
+}
+
+/// whether env `SNDB_AUTH` selects Azure AD tokens, which connections refresh
+fn aad_auth() -> bool {
+    std::env::var("SNDB_AUTH").is_ok_and(|method| method.eq_ignore_ascii_case("aad"))
+}
+
+/// Database pools of each plant, by plant name
+///
+/// Plants are listed, comma separated, in env `SN_PLANTS`, and the first is
+/// used for requests that do not select a plant. The database of each plant
+/// is set by `SNDB_HOST_<PLANT>` and `SNDB_DATABASE_<PLANT>`, with the plant
+/// name in upper case, or by a connection string, see
+/// [`connection_string_config`], and the size of its pool by
+/// `SNDB_POOL_MAX_SIZE_<PLANT>`. If `SN_PLANTS` is not set, the development
+/// database is the only plant, and the variables have no plant suffix.
+///
+/// Each plant can also have a read replica, see [`Replica`].
+#[derive(Debug, Clone)]
+pub struct Plants {
+    default: String,
+    pools: HashMap<String, DbPool>,
+    max_sizes: HashMap<String, u32>,
+    replicas: HashMap<String, Arc<Replica>>,
+}
+
+impl Plants {
+    pub async fn from_env() -> Result<Self> {
+        let names: Vec<String> = std::env::var("SN_PLANTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+
+        if names.is_empty() {
+            log::debug!("using development database config");
+            let max_size = pool_max_size("");
+            let (pool, replica) = plant_pools("", Some((DEV_HOST, DEV_DATABASE)), max_size).await?;
+            return Ok(Self {
+                default: DEFAULT_PLANT.into(),
+                pools: HashMap::from([(DEFAULT_PLANT.into(), pool)]),
+                max_sizes: HashMap::from([(DEFAULT_PLANT.into(), max_size)]),
+                replicas: replica
+                    .map(|replica| (DEFAULT_PLANT.into(), Arc::new(replica)))
+                    .into_iter()
+                    .collect(),
+            });
+        }
+
+        let mut pools = HashMap::new();
+        let mut max_sizes = HashMap::new();
+        let mut replicas = HashMap::new();
+        for name in &names {
+            log::debug!("using database config of plant {}", name);
+            let suffix = format!("_{}", name.to_uppercase());
+            let max_size = pool_max_size(&suffix);
+            let (pool, replica) = plant_pools(&suffix, None, max_size).await?;
+            pools.insert(name.clone(), pool);
+            max_sizes.insert(name.clone(), max_size);
+
+            if let Some(replica) = replica {
+                replicas.insert(name.clone(), Arc::new(replica));
+            }
+        }
+
+        Ok(Self {
+            default: names[0].clone(),
+            pools,
+            max_sizes,
+            replicas,
+        })
+    }
+
+    /// get a plant's pool, or the default plant's if none is given
+    pub fn get(&self, plant: Option<&str>) -> Result<(&str, &DbPool)> {
+        let name = plant.unwrap_or(&self.default);
+
+        self.pools
+            .get_key_value(name)
+            .map(|(name, pool)| (name.as_str(), pool))
+            .ok_or_else(|| Error::NotFound(format!("Plant {} not found", name)))
+    }
+
+    /// all plants and their pools
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &DbPool)> {
+        self.pools.iter().map(|(name, pool)| (name.as_str(), pool))
+    }
+
+    /// maximum number of connections of all plants' pools, not counting read replicas
+    pub fn total_max_size(&self) -> u32 {
+        self.max_sizes.values().sum()
+    }
 
-    // sigmanest interface dev
-    let config = {
-        log::debug!("using development database config");
-        let mut config = tiberius::Config::new();
-        config.host("HIISQLSERV6");
-        config.database("SNDBaseISap");
+    /// maximum number of connections of a plant's pool, and of its read replica's
+    pub fn max_size(&self, plant: &str) -> u32 {
+        self.max_sizes.get(plant).copied().unwrap_or(POOL_MAX_SIZE)
+    }
 
-        // use sql authentication
-        let user = std::env::var("SNDB_USER").unwrap();
-        let pass = std::env::var("SNDB_PWD").unwrap();
-        config.authentication(tiberius::AuthMethod::sql_server(user, pass));
-        config.trust_cert();
+    /// a plant's read replica, if it has one that passed its last health check
+    pub fn replica(&self, plant: &str) -> Option<&Arc<Replica>> {
+        self.replicas
+            .get(plant)
+            .filter(|replica| replica.healthy.load(Ordering::Acquire))
+    }
 
-        config
+    /// check every read replica, so reads fall back to the primary while one is down
+    pub async fn check_replicas(&self) {
+        for (plant, replica) in &self.replicas {
+            replica.check(plant).await;
+        }
+    }
+}
+
+/// Read-only copy of a plant's database, that read requests are sent to
+///
+/// Set by `SNDB_REPLICA_HOST_<PLANT>` and, if its database has another name
+/// than the primary's, `SNDB_REPLICA_DATABASE_<PLANT>`. Without `SN_PLANTS`,
+/// the variables have no plant suffix. Its pool is as large as the primary's.
+/// Reads use the primary until the replica passes a health check, see
+/// [`Plants::check_replicas`], and again from when it fails to connect
+/// until it passes the next one.
+///
+/// Plants connected to with a connection string have no read replica, so
+/// reads are not sent to another database than the one the string is for.
+#[derive(Debug)]
+pub struct Replica {
+    pool: DbPool,
+    healthy: AtomicBool,
+}
+
+impl Replica {
+    async fn from_env(suffix: &str, primary_database: &str, max_size: u32) -> Option<Self> {
+        let host = std::env::var(format!("SNDB_REPLICA_HOST{}", suffix)).ok()?;
+
+        let database = std::env::var(format!("SNDB_REPLICA_DATABASE{}", suffix))
+            .unwrap_or_else(|_| primary_database.into());
+        log::debug!("using read replica {} on {}", database, host);
+
+        // connected lazily, so a replica that is down does not stop the server from starting
+        let config = host_config(&host, &database).await;
+        let mgr = ConnectionManager::new(config, aad_auth());
+        let pool = pool_builder(max_size).build_unchecked(mgr);
+
+        Some(Self {
+            pool,
+            healthy: AtomicBool::new(false),
+        })
+    }
+
+    pub fn pool(&self) -> &DbPool {
+        &self.pool
+    }
+
+    /// mark the replica down after it failed to give a connection, until its next health check
+    pub fn failed(&self, plant: &str) {
+        if self.healthy.swap(false, Ordering::AcqRel) {
+            log::warn!(
+                "Read replica of plant {} failed to connect, serving reads from the primary",
+                plant
+            );
+        }
+    }
+
+    /// run a trivial query on the replica, recording whether it answered
+    async fn check(&self, plant: &str) {
+        let query = async {
+            let mut conn = self.pool.get().await?;
+            conn.simple_query("SELECT 1").await?.into_results().await?;
+            Ok::<_, Error>(())
+        };
+        let healthy = match tokio::time::timeout(REPLICA_CHECK_TIMEOUT, query).await {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                log::debug!(
+                    "Read replica of plant {} failed its health check: {}",
+                    plant,
+                    e
+                );
+                false
+            }
+            Err(_) => false,
+        };
+
+        let was_healthy = self.healthy.swap(healthy, Ordering::AcqRel);
+        match (was_healthy, healthy) {
+            (false, true) => log::info!("Read replica of plant {} is up, serving reads", plant),
+            (true, false) => log::warn!(
+                "Read replica of plant {} is down, serving reads from the primary",
+                plant
+            ),
+            _ => (),
+        }
+    }
+}
+
+/// Pools of a plant's database and of its read replica, if it has one
+///
+/// The database is set by a connection string, or else is `default` if one
+/// is given, or `SNDB_HOST<suffix>` and `SNDB_DATABASE<suffix>` otherwise.
+async fn plant_pools(
+    suffix: &str,
+    default: Option<(&str, &str)>,
+    max_size: u32,
+) -> Result<(DbPool, Option<Replica>)> {
+    if let Some((key, config)) = connection_string_config(suffix)? {
+        log::warn!("using {} instead of database host settings", key);
+        if let Ok(host) = std::env::var(format!("SNDB_REPLICA_HOST{}", suffix)) {
+            log::warn!("Ignoring read replica on {}, as {} is used", host, key);
+        }
+        return Ok((build_db_pool(config, false, max_size).await, None));
+    }
+
+    let (host, database) = match default {
+        Some((host, database)) => (host.into(), database.into()),
+        None => (
+            required_env(&format!("SNDB_HOST{}", suffix))?,
+            required_env(&format!("SNDB_DATABASE{}", suffix))?,
+        ),
+    };
+
+    // fixtures are served without a database, so no credentials are needed
+    if cfg!(feature = "mock") {
+        let pool = build_db_pool(base_config(&host, &database), false, max_size).await;
+        return Ok((pool, None));
+    }
+
+    let pool = build_db_pool(host_config(&host, &database).await, aad_auth(), max_size).await;
+    let replica = Replica::from_env(suffix, &database, max_size).await;
+
+    Ok((pool, replica))
+}
+
+/// value of env `key`, which must be set
+fn required_env(key: &str) -> Result<String> {
+    std::env::var(key).map_err(|_| Error::BadRequest(format!("{} must be set", key)))
+}
+
+/// Database config from a full connection string, with the variable it is from
+///
+/// Read from env `SNDB_CONNECTION_STRING<suffix>`, the plant's own string,
+/// or else `SNDB_CONNECTION_STRING`, which is used for every plant. Strings
+/// starting with `jdbc:` are parsed as JDBC, anything else as ADO.NET.
+/// Meant for quickly pointing the server at another database while testing.
+fn connection_string_config(suffix: &str) -> Result<Option<(String, tiberius::Config)>> {
+    let key = [
+        format!("SNDB_CONNECTION_STRING{}", suffix),
+        "SNDB_CONNECTION_STRING".into(),
+    ]
+    .into_iter()
+    .find(|key| std::env::var_os(key).is_some());
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(None),
     };
+    let conn_str = std::env::var(&key)
+        .map_err(|_| Error::BadRequest(format!("{} is not valid unicode", key)))?;
+
+    let config = if conn_str.starts_with("jdbc:") {
+        tiberius::Config::from_jdbc_string(&conn_str)
+    } else {
+        tiberius::Config::from_ado_string(&conn_str)
+    };
+
+    match config {
+        Ok(config) => Ok(Some((key, config))),
+        Err(e) => Err(Error::BadRequest(format!("Invalid {}: {}", key, e))),
+    }
+}
+
+/// Builds a connection pool for a database
+///
+/// With the `mock` feature, the pool does not connect until it is used, so
+/// the server starts without a database.
+async fn build_db_pool(config: tiberius::Config, aad: bool, max_size: u32) -> DbPool {
+    log::trace!("** init db pool");
 
     // production
     // let config = {
@@ -39,14 +438,16 @@ pub async fn build_db_pool() -> DbPool {
     //     config
     // };
 
-    let mgr = match bb8_tiberius::ConnectionManager::build(config) {
-        Ok(conn_mgr) => conn_mgr,
-        Err(_) => panic!("ConnectionManager failed to connect to database"),
-    };
+    let mgr = ConnectionManager::new(config, aad);
 
     log::trace!("** > db connection Manager built");
 
-    let pool = match bb8::Pool::builder().max_size(8).build(mgr).await {
+    if cfg!(feature = "mock") {
+        log::warn!("Built with the mock feature, not connecting to the database");
+        return pool_builder(max_size).build_unchecked(mgr);
+    }
+
+    let pool = match pool_builder(max_size).build(mgr).await {
         Ok(pool) => pool,
         Err(_) => panic!("database pool failed to build"),
     };
@@ -56,3 +457,33 @@ pub async fn build_db_pool() -> DbPool {
     log::info!("database connected");
     pool
 }
+
+/// Database config of `database` on `host`, without authentication
+fn base_config(host: &str, database: &str) -> tiberius::Config {
+    let mut config = tiberius::Config::new();
+    config.host(host);
+    config.database(database);
+    config.trust_cert();
+
+    config
+}
+
+/// Database config of `database` on `host`, authenticated as set by `SNDB_AUTH`
+async fn host_config(host: &str, database: &str) -> tiberius::Config {
+    let mut config = base_config(host, database);
+    config.authentication(auth_method().await);
+
+    config
+}
+
+/// Pool settings shared by primary and replica pools
+fn pool_builder(max_size: u32) -> bb8::Builder<ConnectionManager> {
+    log::debug!(
+        "pools hold up to {} connections, living for up to {:?}",
+        max_size,
+        conn_max_lifetime()
+    );
+
+    // connections expire on their own lifetime, see `Connection`
+    bb8::Pool::builder().max_size(max_size).max_lifetime(None)
+}
@@ -0,0 +1,144 @@
+use super::DbPool;
+use crate::Result;
+
+/// Tables queried by the server, and the columns it uses of each
+const REQUIRED_TABLES: [(&str, &[&str]); 15] = [
+    ("ProgramMachine", &["ProgramName", "MachineName"]),
+    (
+        "Program",
+        &[
+            "ProgramName",
+            "RepeatID",
+            "ArchivePacketID",
+            "MachineName",
+            "CuttingTime",
+            "SheetName",
+        ],
+    ),
+    (
+        "TransAct",
+        &["TransType", "District", "ProgramName", "ProgramRepeat"],
+    ),
+    (
+        "PIP",
+        &["ProgramName", "WONumber", "PartName", "QtyInProcess"],
+    ),
+    (
+        "Part",
+        &[
+            "PartName",
+            "WONumber",
+            "Data1",
+            "Data2",
+            "TrueArea",
+            "NestedArea",
+            "DueDate",
+        ],
+    ),
+    ("Stock", &["SheetName", "PrimeCode", "Length", "Width"]),
+    (
+        "Remnant",
+        &[
+            "RemnantName",
+            "ProgramName",
+            "Length",
+            "Width",
+            "Area",
+            "Weight",
+            "PrimeCode",
+            "Qty",
+        ],
+    ),
+    (
+        "STPrgArc",
+        &[
+            "ProgramName",
+            "RepeatID",
+            "ArchivePacketID",
+            "TransType",
+            "MachineName",
+            "CuttingTime",
+            "SheetName",
+        ],
+    ),
+    (
+        "STPIPArc",
+        &[
+            "ArchivePacketID",
+            "TransType",
+            "PartName",
+            "WONumber",
+            "QtyInProcess",
+        ],
+    ),
+    // tables owned by the server, see `scripts/schema/server.sql`
+    (
+        "ProgramStateLog",
+        &[
+            "Id",
+            "ProgramName",
+            "Batch",
+            "State",
+            "Operator",
+            "Reason",
+            "LoggedAt",
+        ],
+    ),
+    (
+        "SimTransLog",
+        &[
+            "Id",
+            "TransType",
+            "ProgramName",
+            "ProgramRepeat",
+            "Operator",
+            "PostedAt",
+        ],
+    ),
+    (
+        "FeedbackResolution",
+        &["ArchivePacketID", "Status", "ResolvedAt"],
+    ),
+    (
+        "ProgramPriority",
+        &["ProgramName", "Priority", "Operator", "SetAt"],
+    ),
+    ("HiddenProgram", &["ProgramName", "Operator", "HiddenAt"]),
+    (
+        "STPrtArc",
+        &[
+            "ArchivePacketID",
+            "PartName",
+            "QtyProgram",
+            "Data1",
+            "Data2",
+            "TrueArea",
+            "NestedArea",
+        ],
+    ),
+];
+
+/// check that the tables and columns the server queries exist in a database
+///
+/// Each table is probed with a `select top 0` of its columns, so no rows are read.
+/// Returns a description of every table that failed its probe. If `verbose`,
+/// each probe is logged as it runs.
+pub async fn check_schema(pool: &DbPool, verbose: bool) -> Result<Vec<String>> {
+    let mut conn = pool.get().await?;
+
+    let mut missing = Vec::new();
+    for (table, columns) in REQUIRED_TABLES {
+        let probe = format!("select top 0 {} from {}", columns.join(", "), table);
+        if verbose {
+            log::info!("schema check: {}", probe);
+        }
+
+        let probed = async { conn.simple_query(probe).await?.into_results().await }.await;
+        if let Err(e) = probed {
+            log::error!("Schema check of table {} failed: {}", table, e);
+            missing.push(format!("{} ({})", table, columns.join(", ")));
+        }
+    }
+
+    Ok(missing)
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{db::api::ProgramState, machine::MachineName};
+
+/// Events kept for subscribers that fall behind before they miss some
+pub const EVENT_CAPACITY: usize = 256;
+
+/// Program state change published to live subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramEvent {
+    pub program: String,
+    pub plant: String,
+    /// machine the program is queued on, if it is on one
+    pub machine: Option<String>,
+    pub state: ProgramState,
+    pub batch: Option<String>,
+    pub operator: String,
+    pub at: DateTime<Utc>,
+}
+
+impl ProgramEvent {
+    /// event is for a program queued on a machine
+    pub fn is_for_machine(&self, machine: &MachineName) -> bool {
+        self.machine.as_deref() == Some(machine.as_str())
+    }
+}
+
+/// Broadcast channel of program state changes
+#[derive(Debug, Clone)]
+pub struct Events(broadcast::Sender<ProgramEvent>);
+
+impl Events {
+    pub fn new() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+
+    /// send an event to all current subscribers
+    pub fn publish(&self, event: ProgramEvent) {
+        // sending only fails if nobody is subscribed
+        let _ = self.0.send(event);
+    }
+
+    /// receive events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgramEvent> {
+        self.0.subscribe()
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,95 @@
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequest, Request},
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{db::api::ProgramState, Error, Result};
+
+/// JSON body extractor that rejects bad bodies with an [`Error`]
+///
+/// A body that is not sent as `application/json` is rejected with
+/// [`Error::UnsupportedMediaType`], any other bad body with [`Error::BadRequest`].
+/// A `state` field that is not a valid [`ProgramState`] is rejected with
+/// [`Error::InvalidState`], which lists the allowed states.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self> {
+        let axum::Json(value) = axum::Json::<Value>::from_request(req, state)
+            .await
+            .map_err(|rejection| match rejection {
+                JsonRejection::MissingJsonContentType(_) => {
+                    Error::UnsupportedMediaType(rejection.body_text())
+                }
+                _ => Error::BadRequest(rejection.body_text()),
+            })?;
+
+        if let Some(program_state) = value.get("state").filter(|s| !s.is_null()) {
+            if serde_json::from_value::<ProgramState>(program_state.clone()).is_err() {
+                return Err(Error::InvalidState);
+            }
+        }
+
+        serde_json::from_value(value)
+            .map(Self)
+            .map_err(|e| Error::BadRequest(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, StatusCode},
+        response::IntoResponse,
+    };
+
+    use super::*;
+
+    fn request(content_type: &str, body: &'static str) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/program/1200X-01/state")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn invalid_state_lists_allowed_states() {
+        let req = request("application/json", r#"{"state":"Cutting"}"#);
+        let error = match Json::<Value>::from_request(req, &()).await {
+            Err(error @ Error::InvalidState) => error,
+            Err(other) => panic!("expected invalid state, got {:?}", other),
+            Ok(_) => panic!("expected invalid state to be rejected"),
+        };
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem["allowed"],
+            serde_json::json!(["Initiated", "Processing", "Complete", "Cancelled"])
+        );
+    }
+
+    #[tokio::test]
+    async fn non_json_body_is_unsupported_media_type() {
+        let req = request("text/plain", r#"{"state":"Complete"}"#);
+        match Json::<Value>::from_request(req, &()).await {
+            Err(error) => assert_eq!(error.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            Ok(_) => panic!("expected a text/plain body to be rejected"),
+        }
+    }
+}
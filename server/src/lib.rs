@@ -1,12 +1,51 @@
+pub mod auth;
 pub mod batch;
+pub mod buffer;
+pub mod cache;
+pub mod config;
+pub mod consumption;
 pub mod db;
+pub mod events;
+pub mod extract;
+pub mod limit;
+pub mod lock;
+pub mod logs;
+pub mod machine;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod nc;
+pub mod normalize;
+pub mod pretty;
+pub mod problem;
+pub mod reservation;
+pub mod routes;
+pub mod xml;
 
 pub mod error {
     use axum::{
-        http::StatusCode,
+        http::{header, StatusCode},
         response::{IntoResponse, Response},
     };
 
+    use crate::{db::api::ProgramState, problem::Problem, reservation::Reservation};
+
+    /// Business rule violated by one field of a request
+    #[derive(Debug, serde::Serialize, thiserror::Error)]
+    #[error("{field}: {message}")]
+    pub struct FieldError {
+        pub field: &'static str,
+        pub message: String,
+    }
+
+    impl FieldError {
+        pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+            Self {
+                field,
+                message: message.into(),
+            }
+        }
+    }
+
     // Error handling: see
     //  https://docs.rs/axum/latest/axum/error_handling/index.html
     //  https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs
@@ -16,25 +55,109 @@ pub mod error {
         SqlError(#[from] tiberius::error::Error),
         #[error("Database pool error: see server logs.")]
         SqlPoolError,
+        #[error("Database query timed out")]
+        QueryTimeout,
         #[error("Failed to parse csv file")]
         CsvError,
+        #[error("File system error: see server logs.")]
+        IoError(#[from] std::io::Error),
+        #[error("Failed to serialize XML: see server logs.")]
+        XmlError(#[from] quick_xml::SeError),
         #[error("Requested resource not found")]
         NotFound(String),
+        #[error("Bad request: {0}")]
+        BadRequest(String),
+        #[error("Unsupported media type: {0}")]
+        UnsupportedMediaType(String),
+        #[error("Conflict: {0}")]
+        Conflict(String),
+        #[error("Batches already reserved: {}", .0.iter().map(|r| r.batch.as_str()).collect::<Vec<_>>().join(", "))]
+        ReservationConflicts(Vec<Reservation>),
+        #[error("Missing or invalid API key")]
+        Unauthorized,
+        #[error("Forbidden: {0}")]
+        Forbidden(String),
+        #[error("Server is busy, try again later")]
+        Overloaded,
+        #[error("Temporarily unavailable, retry in {} seconds", retry_after_secs(.0))]
+        Unavailable(std::time::Duration),
+        #[error("Invalid program state")]
+        InvalidState,
+        #[error("SimTrans update failed: {0}")]
+        SimTrans(String),
+        #[error("Invalid request: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))]
+        Validation(Vec<FieldError>),
+    }
+
+    impl Error {
+        pub fn status(&self) -> StatusCode {
+            match self {
+                Self::NotFound(_) => StatusCode::NOT_FOUND,
+                Self::BadRequest(_) | Self::InvalidState => StatusCode::BAD_REQUEST,
+                Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Self::Conflict(_) | Self::ReservationConflicts(_) => StatusCode::CONFLICT,
+                Self::Unauthorized => StatusCode::UNAUTHORIZED,
+                Self::Forbidden(_) => StatusCode::FORBIDDEN,
+                Self::QueryTimeout => StatusCode::GATEWAY_TIMEOUT,
+                Self::Overloaded | Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+                Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            }
+        }
+    }
+
+    impl Error {
+        /// failure to post to SimTrans, with the detail of the error causing it
+        ///
+        /// The detail is of the underlying error, such as the database's, rather
+        /// than the generic message of variants like [`Error::SqlError`].
+        pub fn simtrans(cause: Error) -> Self {
+            let detail = std::error::Error::source(&cause)
+                .map_or_else(|| cause.to_string(), ToString::to_string);
+            Self::SimTrans(detail)
+        }
     }
 
     // Tell axum how to convert `AppError` into a response.
     impl IntoResponse for Error {
         fn into_response(self) -> Response {
-            // let error = match self {
-            //     Self::SqlError(e) => e.to_string(),
-            //     Self::SqlPoolError => todo!(),
-            //     Self::NotFound(s) => s,
-            // };
+            let status = self.status();
+            let problem = match self {
+                // what was not found or is wrong is more useful than the generic message
+                Self::NotFound(detail)
+                | Self::BadRequest(detail)
+                | Self::UnsupportedMediaType(detail)
+                | Self::Forbidden(detail) => Problem::new(status, detail),
+                // the client must know the completion did not reach SimTrans
+                Self::SimTrans(_) => Problem::new(status, self.to_string()),
+                Self::Unavailable(retry_after) => {
+                    let retry_after = retry_after_secs(&retry_after);
+                    let problem =
+                        Problem::new(status, self.to_string()).with("retryAfter", retry_after);
+                    return ([(header::RETRY_AFTER, retry_after.to_string())], problem)
+                        .into_response();
+                }
+                Self::Validation(ref fields) => Problem::new(status, self.to_string())
+                    .with("fields", serde_json::to_value(fields).unwrap()),
+                Self::ReservationConflicts(ref conflicts) => Problem::new(status, self.to_string())
+                    .with("conflicts", serde_json::to_value(conflicts).unwrap()),
+                // list the valid states so clients can discover them from the error
+                Self::InvalidState => {
+                    let allowed: Vec<&str> = ProgramState::ALL.iter().map(|s| s.as_str()).collect();
+                    Problem::new(status, self.to_string()).with("allowed", allowed)
+                }
+                _ => Problem::new(status, self.to_string()),
+            };
 
-            (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            problem.into_response()
         }
     }
 
+    /// whole seconds for a `Retry-After` header, rounded up so clients never retry early
+    fn retry_after_secs(duration: &std::time::Duration) -> u64 {
+        duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+    }
+
     // impl From<SqlError> for Error {
     //     fn from(value: SqlError) -> Self {
     //         log::error!("Casting tiberius error to app error: {:#?}", value);
@@ -55,6 +178,31 @@ pub mod error {
             Self::CsvError
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use axum::body::to_bytes;
+
+        use super::*;
+
+        #[tokio::test]
+        async fn failed_simtrans_insert_is_server_error() {
+            let insert = std::io::Error::other("Cannot insert the value NULL into TransAct");
+            let error = Error::simtrans(Error::IoError(insert));
+            assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+            let response = error.into_response();
+            assert!(!response.status().is_success());
+
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(problem["status"], 500);
+            assert!(problem["detail"]
+                .as_str()
+                .unwrap()
+                .contains("Cannot insert the value NULL"));
+        }
+    }
 }
 
 pub use error::Error;
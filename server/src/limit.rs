@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tokio::sync::Semaphore;
+
+use crate::{Error, Result};
+
+/// Limit on requests handled at once, from env `SN_MAX_CONCURRENT_REQUESTS`
+///
+/// Defaults to twice the connections of all plants' pools, `pool_size`.
+/// Requests over the limit are shed instead of queueing for a database connection.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit(Arc<Semaphore>);
+
+impl ConcurrencyLimit {
+    pub fn from_env(pool_size: u32) -> Self {
+        let limit = std::env::var("SN_MAX_CONCURRENT_REQUESTS")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(pool_size as usize * 2);
+        log::debug!("limiting to {} concurrent requests", limit);
+
+        Self(Arc::new(Semaphore::new(limit)))
+    }
+}
+
+/// middleware rejecting requests over the concurrency limit
+pub async fn shed_load(
+    State(limit): State<ConcurrencyLimit>,
+    request: Request,
+    next: Next,
+) -> Result<Response> {
+    match limit.0.try_acquire() {
+        Ok(_permit) => Ok(next.run(request).await),
+        Err(_) => {
+            log::warn!("Shed request to {}: too many requests", request.uri());
+            Err(Error::Overloaded)
+        }
+    }
+}
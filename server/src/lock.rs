@@ -0,0 +1,31 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Lock of each program, by plant and program
+type LockMap = HashMap<(String, String), Arc<Mutex<()>>>;
+
+/// Locks serializing updates to the same program, keyed by plant and program
+///
+/// Updates to different programs do not wait on each other. Locks no longer
+/// held by anyone are dropped the next time a lock is taken.
+#[derive(Debug, Default)]
+pub struct ProgramLocks(StdMutex<LockMap>);
+
+impl ProgramLocks {
+    /// wait for the lock of a program, which is held until the guard is dropped
+    pub async fn lock(&self, plant: &str, program: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.0.lock().unwrap();
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+            let key = (plant.to_string(), program.to_string());
+            Arc::clone(locks.entry(key).or_default())
+        };
+
+        lock.lock_owned().await
+    }
+}
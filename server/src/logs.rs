@@ -0,0 +1,89 @@
+use std::io::SeekFrom;
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::broadcast,
+};
+
+use crate::Result;
+
+/// Log file written by the server, relative to its working directory
+pub const LOG_FILE: &str = "server.log";
+
+/// Most lines of the log file returned at once
+pub const MAX_TAIL_LINES: usize = 5000;
+
+/// Bytes read at a time while searching backwards for lines
+const TAIL_CHUNK_SIZE: u64 = 8 * 1024;
+
+/// Log lines kept for live subscribers that fall behind, before the oldest are dropped
+pub const LOG_STREAM_CAPACITY: usize = 1024;
+
+/// read the last `lines` lines of the log file, at least one
+///
+/// The file is read backwards from its end in chunks, so only the tail is
+/// loaded no matter how large the log has grown.
+pub async fn tail(lines: usize) -> Result<String> {
+    let mut file = File::open(LOG_FILE).await?;
+    let len = file.metadata().await?.len();
+
+    // a trailing newline ends the last line rather than starting an empty one
+    let mut pos = len;
+    let mut buf = Vec::new();
+    let mut newlines = 0;
+    while pos > 0 && newlines <= lines {
+        let size = TAIL_CHUNK_SIZE.min(pos);
+        pos -= size;
+
+        let mut chunk = vec![0; size as usize];
+        file.seek(SeekFrom::Start(pos)).await?;
+        file.read_exact(&mut chunk).await?;
+
+        newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+        chunk.append(&mut buf);
+        buf = chunk;
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    let body = text.strip_suffix('\n').unwrap_or(&text);
+    let start = match body.rmatch_indices('\n').nth(lines.saturating_sub(1)) {
+        Some((i, _)) => i + 1,
+        None => 0,
+    };
+
+    Ok(text[start..].to_string())
+}
+
+/// Broadcast channel of log lines as they are written
+///
+/// Subscribers that fall more than [`LOG_STREAM_CAPACITY`] lines behind miss
+/// the oldest lines rather than holding up logging.
+#[derive(Debug, Clone)]
+pub struct LogStream(broadcast::Sender<String>);
+
+impl LogStream {
+    pub fn new() -> Self {
+        Self(broadcast::channel(LOG_STREAM_CAPACITY).0)
+    }
+
+    /// logger output sending formatted lines to all current subscribers
+    pub fn output(&self) -> fern::Output {
+        let sender = self.0.clone();
+        fern::Output::call(move |record| {
+            // sending only fails if nobody is subscribed
+            let _ = sender.send(record.args().to_string());
+        })
+    }
+
+    /// receive log lines written from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.0.subscribe()
+    }
+}
+
+impl Default for LogStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
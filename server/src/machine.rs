@@ -0,0 +1,157 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Longest machine name accepted, matching `ProgramMachine.MachineName`
+pub const MACHINE_NAME_MAX_LEN: usize = 50;
+
+/// Validated machine name
+///
+/// Machine names are non-empty, at most [`MACHINE_NAME_MAX_LEN`] characters,
+/// and only contain ASCII letters, digits, spaces, `-` and `_`, so they are
+/// also safe to use as NC directory names. Deserializing an invalid name
+/// fails, so `Path<MachineName>` rejects it with a 400.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MachineName(String);
+
+impl MachineName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for MachineName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_');
+
+        if s.trim().is_empty() {
+            Err(Error::BadRequest("Machine name cannot be empty".into()))
+        } else if s.len() > MACHINE_NAME_MAX_LEN {
+            Err(Error::BadRequest(format!(
+                "Machine name cannot be longer than {} characters",
+                MACHINE_NAME_MAX_LEN
+            )))
+        } else if !s.chars().all(valid_char) {
+            Err(Error::BadRequest(format!(
+                "Machine name `{}` contains invalid characters",
+                s
+            )))
+        } else {
+            Ok(Self(s.into()))
+        }
+    }
+}
+
+impl TryFrom<String> for MachineName {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<MachineName> for String {
+    fn from(value: MachineName) -> Self {
+        value.0
+    }
+}
+
+impl AsRef<str> for MachineName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for MachineName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MachineStatus {
+    Online,
+    Offline,
+}
+
+/// Whether a machine has programs queued, so an empty queue is explicit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MachineActivity {
+    /// nothing queued
+    Idle,
+    /// at least one program queued
+    Active,
+}
+
+impl MachineActivity {
+    pub fn of_queue<T>(programs: &[T]) -> Self {
+        match programs.is_empty() {
+            true => MachineActivity::Idle,
+            false => MachineActivity::Active,
+        }
+    }
+}
+
+/// Machine taken out of service, and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Downtime {
+    pub machine: MachineName,
+    pub reason: Option<String>,
+    pub operator: String,
+    pub since: DateTime<Utc>,
+}
+
+/// In-memory store of offline machines, keyed by plant and machine
+///
+/// Machines not in the store are online. The store is kept apart from the
+/// machine cache, so refreshing machines does not bring them back online.
+#[derive(Debug, Default)]
+pub struct MachineStatuses(HashMap<(String, MachineName), Downtime>);
+
+impl MachineStatuses {
+    /// take a machine out of service, replacing any previous reason
+    pub fn set_offline(
+        &mut self,
+        plant: &str,
+        machine: &MachineName,
+        reason: Option<&str>,
+        operator: &str,
+    ) -> Downtime {
+        let downtime = Downtime {
+            machine: machine.clone(),
+            reason: reason.map(Into::into),
+            operator: operator.into(),
+            since: Utc::now(),
+        };
+        self.0
+            .insert((plant.into(), machine.clone()), downtime.clone());
+
+        downtime
+    }
+
+    /// put a machine back in service, returning its downtime if it was offline
+    pub fn set_online(&mut self, plant: &str, machine: &MachineName) -> Option<Downtime> {
+        self.0.remove(&(plant.into(), machine.clone()))
+    }
+
+    /// get the downtime of a machine, if it is offline
+    pub fn downtime(&self, plant: &str, machine: &MachineName) -> Option<&Downtime> {
+        self.0.get(&(plant.into(), machine.clone()))
+    }
+
+    /// check if a machine, by name, is offline
+    pub fn is_offline(&self, plant: &str, machine: &str) -> bool {
+        self.0
+            .keys()
+            .any(|(offline_plant, offline)| offline_plant == plant && offline.as_str() == machine)
+    }
+}
@@ -1,304 +1,3559 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    future::IntoFuture,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex, RwLock,
+    },
+};
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    routing::get,
-    Router,
+    async_trait,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRequestParts, Path, Query, Request, State,
+    },
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+    routing::{delete, get, patch, post, MethodRouter},
+    Router, ServiceExt,
 };
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use serde_json::{json, Value};
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        MappedMutexGuard, Mutex, MutexGuard, Semaphore,
+    },
+    task::JoinSet,
+    time::{sleep, Instant},
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tokio_util::sync::CancellationToken;
+use tower::Layer;
+use tower_http::{decompression::RequestDecompressionLayer, normalize_path::NormalizePathLayer};
 
 use sigmanest_interface::{
-    batch::Batch,
+    auth::{require_write_key, Operator, WriteKey},
+    batch::{
+        self, Batch, BatchError, Material, MaterialDemand, BATCHES_LOADED_HEADER,
+        BATCHES_SKIPPED_HEADER, BATCH_SOURCE_COOLDOWN,
+    },
+    buffer::SimTransBuffer,
+    cache,
+    config::{config_file, BatchesEmptyMode, Config},
+    consumption::ConsumptionLedger,
     db::{
         self,
-        api::{FeedbackEntry, Nest},
-        exports::export_feedback,
+        api::{
+            simtrans, BoundingBox, FeedbackEntry, HiddenProgram, MachineProgram, MachineThroughput,
+            Nest, PendingSimTrans, PostedTransaction, Program, ProgramPriority, ProgramState,
+            ProgramStatus, ProgramTiming, QueueChange, QueueEstimate, QueuePosition, QueuedProgram,
+            RelatedProgram, RemnantEstimate, Resolution, Sheet, StateLogEntry, Utilization,
+            WorkOrderProgram,
+        },
+        exports::{
+            export_feedback, export_feedback_by_part, export_feedback_page, FeedbackQuery,
+            PartFeedbackCount,
+        },
     },
-    Result,
+    error::FieldError,
+    events::{Events, ProgramEvent},
+    extract,
+    limit::{shed_load, ConcurrencyLimit},
+    lock::ProgramLocks,
+    logs::{self, LogStream},
+    machine::{MachineActivity, MachineName, MachineStatus, MachineStatuses},
+    nc::{self, PendingMove, PendingMoves},
+    normalize::{normalize_path, PathNormalization},
+    pretty::{pretty_json, PrettyJson},
+    problem::problem_responses,
+    reservation::{reservation_ttl, Reservation, Reservations},
+    routes::{AuthLevel, RouteInfo, ROUTES},
+    xml, Error, Result,
 };
 
+/// Most programs that can be requested from `/nests` or cancelled at once
+const MAX_NESTS_PER_REQUEST: usize = 100;
+
+/// Most days of SimTrans transactions that can be requested at once
+const MAX_TRANSACTION_RANGE_DAYS: i64 = 31;
+
+/// Most days a throughput report can cover
+const MAX_REPORT_RANGE_DAYS: i64 = 366;
+
+/// Sheets of a batch used by completing a program, which completes one repeat
+const SHEETS_PER_COMPLETION: u32 = 1;
+
+/// Longest batch name, matching `ProgramStateLog.Batch`
+const BATCH_MAX_LEN: usize = 50;
+
+/// Lines of the server log returned by `/admin/logs` if not requested
+const DEFAULT_LOG_TAIL_LINES: usize = 200;
+
 #[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct ProgramUpdateParams {
+    #[serde(default)]
     batch: String,
     state: ProgramState,
+    /// SimTrans transaction type the completion is posted as
+    trans_type: Option<String>,
 }
 
-#[derive(Debug, serde::Deserialize)]
-enum ProgramState {
-    Initiated,
-    Processing,
-    Complete,
-    Cancelled,
+impl ProgramUpdateParams {
+    /// check rules across fields that deserializing cannot
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        let needs_batch = matches!(
+            self.state,
+            ProgramState::Processing | ProgramState::Complete
+        );
+        if needs_batch && self.batch.trim().is_empty() {
+            errors.push(FieldError::new(
+                "batch",
+                format!("required for state {}", self.state.as_str()),
+            ));
+        }
+        if self.batch.len() > BATCH_MAX_LEN {
+            errors.push(FieldError::new(
+                "batch",
+                format!("cannot be longer than {} characters", BATCH_MAX_LEN),
+            ));
+        }
+        if self.trans_type.is_some() && self.state != ProgramState::Complete {
+            errors.push(FieldError::new(
+                "transType",
+                format!("only applies to state {}", ProgramState::Complete.as_str()),
+            ));
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Validation(errors)),
+        }
+    }
 }
 
-#[derive(Debug)]
-struct AppState {
-    pub db: db::DbPool,
-    pub batches: Mutex<Option<Vec<Batch>>>,
+#[derive(Debug, serde::Deserialize)]
+struct ProgramPatchParams {
+    batch: Option<String>,
+    state: Option<ProgramState>,
 }
 
-impl AppState {
-    pub async fn new() -> Self {
-        Self {
-            db: db::build_db_pool().await,
-            batches: Mutex::new(None),
+impl ProgramPatchParams {
+    fn validate(&self) -> Result<()> {
+        match (&self.batch, &self.state) {
+            (None, None) => Err(Error::BadRequest(
+                "At least one of `batch` or `state` must be provided".into(),
+            )),
+            _ => Ok(()),
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> std::result::Result<(), std::io::Error> {
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{} {} {}] {}",
-                humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Error)
-        .level_for("sigmanest_interface", log::LevelFilter::Trace)
-        .chain(
-            fern::Dispatch::new()
-                .level(log::LevelFilter::Debug)
-                .chain(std::io::stdout()),
-        )
-        .chain(
-            fern::Dispatch::new().level(log::LevelFilter::Trace).chain(
-                std::fs::OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open("server.log")?,
-            ),
-        )
-        .apply()
-        .expect("failed to init logging");
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProgramSort {
+    DueDate,
+}
 
-    let state = Arc::new(AppState::new().await);
+#[derive(Debug, serde::Deserialize)]
+struct ProgramListParams {
+    sort: Option<ProgramSort>,
+    /// also list hidden programs
+    #[serde(default)]
+    include_hidden: bool,
+}
 
-    // build our application with a single route
-    let app = Router::new()
-        .route("/", get(|| async { "root request not implemented yet" }))
-        .route("/machines", get(get_machines))
-        .route("/batches", get(get_batches))
-        .route("/batches/:program", get(get_batches_for_program))
-        .route("/:machine", get(get_programs))
-        .route("/nest/:nest", get(get_nest).post(update_program))
-        .route("/feedback", get(get_feedback))
-        .with_state(state);
+#[derive(Debug, serde::Deserialize)]
+struct BatchMatchParams {
+    /// size difference allowed for batches of the nest's grade
+    tolerance: Option<f64>,
+    /// load batches from the batch source for this request only, rather than
+    /// matching the possibly stale cache, at most once per cooldown
+    #[serde(default)]
+    fresh: bool,
+}
 
-    // run our app with hyper, listening globally on port 3080
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3080").await?;
-    axum::serve(listener, app).await
+/// Batch for a program, and if it is for the program's exact sheet
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchMatch {
+    #[serde(flatten)]
+    batch: Batch,
+    exact_match: bool,
 }
 
-async fn get_machines(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
-    log::debug!("Requested machines list");
+#[derive(Debug, serde::Deserialize)]
+struct ValidateParams {
+    batch: Option<String>,
+}
 
-    let state = Arc::clone(&state);
+/// Longest cancellation reason, matching `ProgramStateLog.Reason`
+const REASON_MAX_LEN: usize = 255;
 
-    let mut conn = state.db.get_owned().await.unwrap();
-    let results = conn
-        .simple_query("select distinct MachineName from ProgramMachine")
-        .await;
-    match results {
-        Ok(stream) => match stream.into_first_result().await {
-            Ok(rows) => {
-                let machines: Vec<String> = rows
-                    .iter()
-                    .map(|row| row.get::<&str, _>(0))
-                    .map(|val| String::from(val.unwrap_or("")))
-                    .collect();
-
-                (StatusCode::OK, Json(json!(machines)))
-            }
-            Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Value::Null)),
-        },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Value::Null)),
+/// message describing why a request failed, without the error kind prefix
+fn error_detail(e: Error) -> String {
+    match e {
+        Error::NotFound(detail) | Error::BadRequest(detail) | Error::Conflict(detail) => detail,
+        e => e.to_string(),
     }
 }
 
-async fn get_batches(State(state): State<Arc<AppState>>) -> Result<(StatusCode, Json<Vec<Batch>>)> {
-    log::debug!("Requested batches list");
+/// Result of one prerequisite check for completing a program
+#[derive(Debug, serde::Serialize)]
+struct CompletionCheck {
+    check: &'static str,
+    ok: bool,
+    detail: Option<String>,
+}
 
-    let state = Arc::clone(&state);
-    let mut batches = state.batches.lock().await;
+impl CompletionCheck {
+    fn new(check: &'static str, result: Result<()>) -> Self {
+        let detail = result.err().map(error_detail);
 
-    if let None = *batches {
-        // load batches from data source
-        *batches = Some(Batch::get_batches()?);
+        Self {
+            check,
+            ok: detail.is_none(),
+            detail,
+        }
     }
+}
 
-    Ok((StatusCode::OK, Json(batches.as_ref().unwrap().clone())))
+#[derive(Debug, serde::Deserialize)]
+struct CancelParams {
+    programs: Vec<String>,
+    reason: String,
 }
 
-async fn get_batches_for_program(
-    State(state): State<Arc<AppState>>,
-    Path(program): Path<String>,
-) -> Result<(StatusCode, Json<Vec<Batch>>)> {
-    log::debug!("Requested batches list for program `{}`", program);
+impl CancelParams {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
 
-    let state = Arc::clone(&state);
+        if self.programs.is_empty() || self.programs.len() > MAX_NESTS_PER_REQUEST {
+            errors.push(FieldError::new(
+                "programs",
+                format!("must list 1 to {} programs", MAX_NESTS_PER_REQUEST),
+            ));
+        }
+        if self.reason.trim().is_empty() {
+            errors.push(FieldError::new("reason", "required"));
+        }
+        if self.reason.len() > REASON_MAX_LEN {
+            errors.push(FieldError::new(
+                "reason",
+                format!("cannot be longer than {} characters", REASON_MAX_LEN),
+            ));
+        }
 
-    let mut batches = state.batches.lock().await;
-    if let None = *batches {
-        // load batches from data source
-        *batches = Some(Batch::get_batches()?);
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Validation(errors)),
+        }
     }
+}
 
-    let mut conn = state.db.get_owned().await.unwrap();
-    let nest = Nest::get(&mut conn, &program).await?;
+/// Result of cancelling one program of a bulk cancellation
+#[derive(Debug, serde::Serialize)]
+struct CancelResult {
+    program: String,
+    cancelled: bool,
+    error: Option<String>,
+}
 
-    // TODO: handle nested on singleton sheet
+/// Data the nest report template renders
+///
+/// There is no report generator in the server, so a reprint returns what
+/// the printed paperwork needs for the client to render it.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NestReport {
+    #[serde(flatten)]
+    nest: Nest,
+    status: ProgramStatus,
+    requested_by: String,
+    generated_at: NaiveDateTime,
+}
 
-    let mm_batches = batches
-        .as_ref()
-        .unwrap()
-        .iter()
-        .filter(|bat| bat.sheet_name == nest.sheet.sheet_name)
-        .map(|bat| bat.clone())
-        .collect();
+/// Machine in the queue snapshot
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotMachine<'a> {
+    machine: String,
+    status: MachineStatus,
+    programs: Vec<SnapshotProgram<'a>>,
+    /// programs were cut off at the configured most programs per machine
+    truncated: bool,
+}
 
-    Ok((StatusCode::OK, Json(mm_batches)))
+/// Program in the queue snapshot, with the batches it can be cut from
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotProgram<'a> {
+    #[serde(flatten)]
+    program: MachineProgram,
+    sheet: Option<&'a Sheet>,
+    batches: Vec<&'a Batch>,
 }
 
-async fn get_feedback(
-    State(state): State<Arc<AppState>>,
-) -> Result<(StatusCode, Json<Vec<FeedbackEntry<Nest>>>)> {
-    log::debug!("Requested feedback");
+/// Programs of a machine nested on the same sheet
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SheetGroup {
+    /// sheet of the programs, if it could be found
+    sheet: Option<Sheet>,
+    programs: Vec<MachineProgram>,
+}
 
-    let state = Arc::clone(&state);
+#[derive(Debug, serde::Deserialize)]
+struct EventParams {
+    /// only send events of programs queued on this machine
+    machine: Option<MachineName>,
+}
 
-    let feedback = export_feedback(state.db.clone()).await?;
+#[derive(Debug, serde::Deserialize)]
+struct NestParams {
+    /// repeat id of the program, defaulting to the first repeat
+    repeat: Option<i32>,
+    /// comma separated top-level fields to return, defaulting to all of them
+    fields: Option<String>,
+}
 
-    Ok((StatusCode::OK, Json(feedback)))
+#[derive(Debug, serde::Deserialize)]
+struct NestsParams {
+    programs: Vec<String>,
 }
 
-async fn get_programs(
-    State(state): State<Arc<AppState>>,
-    Path(machine): Path<String>,
-) -> (StatusCode, Json<Value>) {
-    log::debug!("Requested programs for machine {}", machine);
+#[derive(Debug, serde::Deserialize)]
+struct TransactionRangeParams {
+    since: NaiveDate,
+    until: Option<NaiveDate>,
+    r#type: Option<String>,
+}
 
-    let state = Arc::clone(&state);
+impl TransactionRangeParams {
+    /// get the validated `[since, until)` range, with `until` including its whole day
+    fn range(&self) -> Result<(NaiveDateTime, NaiveDateTime)> {
+        date_range(self.since, self.until, MAX_TRANSACTION_RANGE_DAYS)
+    }
+}
 
-    let mut conn = state.db.get_owned().await.unwrap();
-    let results = conn
-        .query(
-            r#"
-SELECT DISTINCT
-    ProgramName,
-    CuttingTime,
-    rpt.Repeats
-FROM ProgramMachine
-INNER JOIN (
-    SELECT
-		ProgramName AS p,
-		COUNT(RepeatID) AS Repeats
-    FROM Program
-    WHERE NOT EXISTS (
-        SELECT 1
-        FROM TransAct
-        WHERE TransType = 'SN70'
-        AND TransAct.ProgramName=Program.ProgramName
-        AND TransAct.ProgramRepeat=Program.RepeatId
-    )
-    GROUP BY ProgramName
-) AS rpt
-    ON rpt.p=ProgramMachine.ProgramName
-WHERE MachineName=@P1
-AND rpt.Repeats > 0
-            "#,
-            &[&machine],
-        )
-        .await;
-    match results {
-        Ok(stream) => match stream.into_first_result().await {
-            Ok(rows) => {
-                let programs: Vec<Value> = rows
-                    .iter()
-                    .map(|row| {
-                        json!({
-                            "program": row.get::<&str, _>("ProgramName").unwrap(),
-                            "repeats": row.get::<i32, _>("Repeats").unwrap(),
-                            "cuttingTime": row.get::<f64, _>("CuttingTime").unwrap()
-                        })
-                    })
-                    .collect();
+#[derive(Debug, serde::Deserialize)]
+struct ThroughputParams {
+    machine: Option<MachineName>,
+    since: NaiveDate,
+    until: Option<NaiveDate>,
+}
+
+/// validate a range of days, returning it as `[since, until)` with `until` including its whole day
+///
+/// `until` defaults to today, and the range may cover at most `max_days`.
+fn date_range(
+    since: NaiveDate,
+    until: Option<NaiveDate>,
+    max_days: i64,
+) -> Result<(NaiveDateTime, NaiveDateTime)> {
+    let until = until.unwrap_or_else(|| Local::now().date_naive());
+    if until < since {
+        return Err(Error::BadRequest("`until` is before `since`".into()));
+    }
+    if (until - since).num_days() >= max_days {
+        return Err(Error::BadRequest(format!(
+            "At most {} days may be requested at once",
+            max_days
+        )));
+    }
+
+    Ok((
+        since.and_time(NaiveTime::MIN),
+        (until + Days::new(1)).and_time(NaiveTime::MIN),
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SnapshotDiffParams {
+    from: NaiveDateTime,
+    /// defaults to now
+    to: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FeedbackByPartParams {
+    /// split counts by feedback type
+    #[serde(default)]
+    by_type: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PriorityParams {
+    priority: i32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ResolveParams {
+    status: Resolution,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MachineListParams {
+    /// only list machines that are online
+    #[serde(default)]
+    active: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LogTailParams {
+    /// lines from the end of the log, defaulting to [`DEFAULT_LOG_TAIL_LINES`]
+    lines: Option<usize>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MachineStatusParams {
+    status: MachineStatus,
+    reason: Option<String>,
+}
 
-                (StatusCode::OK, Json(json!(programs)))
+impl MachineStatusParams {
+    fn validate(&self) -> Result<()> {
+        match &self.reason {
+            Some(reason) if reason.len() > REASON_MAX_LEN => {
+                Err(Error::Validation(vec![FieldError::new(
+                    "reason",
+                    format!("cannot be longer than {} characters", REASON_MAX_LEN),
+                )]))
             }
-            Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Value::Null)),
-        },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Value::Null)),
+            _ => Ok(()),
+        }
     }
 }
 
-async fn get_nest(
-    State(state): State<Arc<AppState>>,
-    Path(program): Path<String>,
-) -> Result<(StatusCode, Json<Value>)> {
-    log::debug!("Requested program {}", program);
+#[derive(Debug, serde::Deserialize)]
+struct MachineAssignParams {
+    machine: MachineName,
+}
 
-    let state = Arc::clone(&state);
-    let mut conn = state.db.get_owned().await.unwrap();
-    let nest = Nest::get(&mut conn, &program).await?;
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReservationParams {
+    holder: String,
+    ttl_seconds: Option<i64>,
+}
 
-    log::debug!("Nest found");
-    Ok((StatusCode::OK, Json(serde_json::to_value(nest).unwrap())))
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkReservationParams {
+    batches: Vec<String>,
+    holder: String,
+    ttl_seconds: Option<i64>,
 }
 
-async fn update_program(
-    State(state): State<Arc<AppState>>,
-    Path(program): Path<String>,
-    Json(params): Json<ProgramUpdateParams>,
-) -> (StatusCode, Json<Value>) {
-    // TODO: log processing changes to database
+/// Machine each program is queued on and when it was looked up, by plant and program
+type ProgramMachines = HashMap<(String, String), (Option<String>, Instant)>;
 
-    match params.state {
-        ProgramState::Initiated => log::trace!("Program {} initiated", program),
-        ProgramState::Processing => {
-            // TODO: move NC"
-            log::trace!(
-                "Program {} is moved to processing with batch {}",
-                program,
-                params.batch
-            );
+/// Time a program's machine is cached, as programs can be moved outside this server
+const PROGRAM_MACHINE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Time a plant's machines are cached, so machines added to the database are
+/// listed even if `SN_CACHE_REFRESH_SECS` is not set
+const MACHINES_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+struct AppState {
+    pub plants: db::Plants,
+    pub batches: Mutex<Option<Vec<Batch>>>,
+    /// malformed records skipped by the last load of the batch source
+    pub batches_skipped: AtomicUsize,
+    /// time the cached batches were loaded from the batch source
+    pub batches_loaded_at: StdMutex<Option<DateTime<Utc>>>,
+    pub reservations: Mutex<Reservations>,
+    pub ready: AtomicBool,
+    pub simtrans_enabled: AtomicBool,
+    /// completions waiting to be posted together, if buffering is configured,
+    /// or until SimTrans is resumed
+    pub simtrans_buffer: SimTransBuffer,
+    pub config: RwLock<Arc<Config>>,
+    /// earliest time to read the batch source again after it was unavailable
+    pub batches_retry_at: StdMutex<Option<Instant>>,
+    /// when batches were last loaded for a single request, see `?fresh=true`
+    pub fresh_batches_at: StdMutex<Option<Instant>>,
+    /// machines of each plant and when they were loaded, if loaded
+    pub machines: RwLock<HashMap<String, (Vec<String>, Instant)>>,
+    pub events: Events,
+    pub machine_statuses: Mutex<MachineStatuses>,
+    /// machine each program is queued on, by plant and program, for events
+    pub program_machines: RwLock<ProgramMachines>,
+    pub program_locks: ProgramLocks,
+    pub log_stream: LogStream,
+    /// NC moves that failed and are retried in the background
+    pub pending_nc_moves: Mutex<PendingMoves>,
+    /// sheets consumed from batches, taken off batches when they are reloaded
+    pub consumption: Mutex<ConsumptionLedger>,
+}
+
+impl AppState {
+    pub async fn new(config: Config, log_stream: LogStream) -> Result<Self> {
+        Ok(Self {
+            plants: db::Plants::from_env().await?,
+            batches: Mutex::new(None),
+            batches_skipped: AtomicUsize::new(0),
+            batches_loaded_at: StdMutex::new(None),
+            reservations: Mutex::new(Reservations::default()),
+            ready: AtomicBool::new(false),
+            simtrans_enabled: AtomicBool::new(true),
+            simtrans_buffer: SimTransBuffer::load().await,
+            config: RwLock::new(Arc::new(config)),
+            batches_retry_at: StdMutex::new(None),
+            fresh_batches_at: StdMutex::new(None),
+            machines: RwLock::new(HashMap::new()),
+            events: Events::new(),
+            machine_statuses: Mutex::new(MachineStatuses::default()),
+            program_machines: RwLock::new(HashMap::new()),
+            program_locks: ProgramLocks::default(),
+            log_stream,
+            pending_nc_moves: Mutex::new(PendingMoves::load().await),
+            consumption: Mutex::new(ConsumptionLedger::load().await),
+        })
+    }
+
+    /// get the current config
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().unwrap())
+    }
+
+    /// get the batch cache, loading it from the data source if it is empty
+    pub async fn batches(&self) -> Result<MappedMutexGuard<'_, Vec<Batch>>> {
+        let mut batches = self.batches.lock().await;
+        if batches.is_none() {
+            // load batches from data source
+            *batches = Some(self.load_batches().await?);
+            self.ready.store(true, Ordering::Release);
+        }
+
+        Ok(MutexGuard::map(batches, |batches| {
+            batches.as_mut().unwrap()
+        }))
+    }
+
+    /// reload the batch cache from the data source, returning the number of batches
+    pub async fn refresh_batches(&self) -> Result<usize> {
+        let batches = self.load_batches().await?;
+        let count = batches.len();
+
+        *self.batches.lock().await = Some(batches);
+        self.ready.store(true, Ordering::Release);
+
+        Ok(count)
+    }
+
+    /// get a plant's machines, loading them from the database if they are not
+    /// cached, or were cached more than [`MACHINES_TTL`] ago
+    pub async fn machines(&self, db: &PlantDb) -> Result<Vec<String>> {
+        if let Some((machines, loaded)) = self.machines.read().unwrap().get(&db.plant) {
+            if loaded.elapsed() < MACHINES_TTL {
+                return Ok(machines.clone());
+            }
+        }
+
+        let mut conn = db.conn().await?;
+        let machines = db::timed(conn.breaker(), MachineProgram::get_machines(&mut conn)).await?;
+        self.machines
+            .write()
+            .unwrap()
+            .insert(db.plant.clone(), (machines.clone(), Instant::now()));
+
+        Ok(machines)
+    }
+
+    /// reload the machines of every plant, keeping the cached machines of plants that fail
+    pub async fn refresh_machines(&self) {
+        for (plant, pool) in self.plants.iter() {
+            let loaded = async {
+                let mut conn = pool.get_owned().await?;
+                db::timed(conn.breaker(), MachineProgram::get_machines(&mut conn)).await
+            }
+            .await;
+
+            match loaded {
+                Ok(machines) => {
+                    log::trace!("refreshed {} machines of plant {}", machines.len(), plant);
+                    self.machines
+                        .write()
+                        .unwrap()
+                        .insert(plant.into(), (machines, Instant::now()));
+                }
+                Err(e) => {
+                    log::error!("Failed to refresh machines of plant {}", plant);
+                    log::error!("{:#?}", e);
+                }
+            }
+        }
+    }
+
+    /// take sheets consumed by a completed program from a cached batch, returning the sheets left
+    ///
+    /// Exhausted batches are removed from the cache so they are no longer offered.
+    /// The consumption is recorded in the [`ConsumptionLedger`], so it is taken
+    /// off again if batches are reloaded before the batch source reflects it.
+    pub async fn consume_batch(&self, batch: &str, sheets: u32) -> Result<u32> {
+        let mut batches = self.batches().await?;
+        let index = batches
+            .iter()
+            .position(|bat| bat.id == batch)
+            .ok_or_else(|| Error::NotFound(format!("Batch {} not found", batch)))?;
+
+        let remaining = batches[index].consume(sheets);
+        self.consumption.lock().await.record(batch, sheets).await;
+        if remaining == 0 {
+            log::info!("Batch {} is used up", batch);
+            batches.remove(index);
+        }
+
+        if let Err(e) = cache::save_batches(&batches).await {
+            log::error!("Failed to save batch cache");
+            log::error!("{:#?}", e);
         }
-        ProgramState::Complete => {
-            log::info!("Program {} complete with batch {}", program, params.batch);
-
-            // issue SimTrans update
-            let state = Arc::clone(&state);
-            let mut conn = state.db.get_owned().await.unwrap();
-            let update = conn
-                .execute(
-                    r#"
-INSERT INTO TransAct(TransType,District,ProgramName,ProgramRepeat)
-VALUES (
-    SELECT TOP 1
-        'SN70',1,@P1,RepeatId
-    FROM Program
-    WHERE ProgramName=@P1
-)
-                    "#,
-                    &[&program],
-                )
-                .await;
 
-            if let Err(e) = update {
-                log::error!("Failed to push program update to SimTrans");
+        Ok(remaining)
+    }
+
+    /// get the machine a program is queued on, caching it for later events
+    ///
+    /// Machines are cached for [`PROGRAM_MACHINE_TTL`], and expired entries are
+    /// dropped whenever one is added, so the cache only holds recent programs.
+    async fn program_machine(&self, db: &PlantDb, program: &str) -> Result<Option<String>> {
+        let key = (db.plant.clone(), program.to_string());
+        if let Some((machine, looked_up)) = self.program_machines.read().unwrap().get(&key) {
+            if looked_up.elapsed() < PROGRAM_MACHINE_TTL {
+                return Ok(machine.clone());
+            }
+        }
+
+        let mut conn = db.conn().await?;
+        let machine = db::timed(conn.breaker(), Program::get_machine(&mut conn, program)).await?;
+        self.cache_program_machine(key, machine.clone());
+
+        Ok(machine)
+    }
+
+    /// cache the machine of a program, see [`Self::program_machine`]
+    fn cache_program_machine(&self, key: (String, String), machine: Option<String>) {
+        let mut machines = self.program_machines.write().unwrap();
+        machines.retain(|_, (_, looked_up)| looked_up.elapsed() < PROGRAM_MACHINE_TTL);
+        machines.insert(key, (machine, Instant::now()));
+    }
+
+    /// publish a program state change to live subscribers
+    async fn publish_state(
+        &self,
+        db: &PlantDb,
+        operator: &Operator,
+        program: &str,
+        batch: Option<&str>,
+        state: ProgramState,
+    ) {
+        // subscribers filtering by machine miss the event if it cannot be found
+        let machine = match self.program_machine(db, program).await {
+            Ok(machine) => machine,
+            Err(e) => {
+                log::error!("Failed to find machine of program {} for event", program);
                 log::error!("{:#?}", e);
+                None
             }
+        };
+
+        self.events.publish(ProgramEvent {
+            program: program.into(),
+            plant: db.plant.clone(),
+            machine,
+            state,
+            batch: batch.map(Into::into),
+            operator: operator.to_string(),
+            at: chrono::Utc::now(),
+        });
+    }
+
+    /// load batches for the cache, saving them to the cache file
+    ///
+    /// The cache file is written on every load rather than at shutdown,
+    /// so it is also kept if the server is stopped abruptly. The number of
+    /// malformed records skipped is kept for `GET /batches`.
+    async fn load_batches(&self) -> Result<Vec<Batch>> {
+        let (batches, skipped) = self.read_batch_source().await?;
+        if let Err(e) = cache::save_batches(&batches).await {
+            log::error!("Failed to save batch cache");
+            log::error!("{:#?}", e);
         }
-        ProgramState::Cancelled => log::trace!("Program {} cancelled", program),
+
+        self.batches_skipped.store(skipped.len(), Ordering::Release);
+        *self.batches_loaded_at.lock().unwrap() = Some(Utc::now());
+        Ok(batches)
+    }
+
+    /// load batches for a single request, without replacing the cache
+    ///
+    /// Fails with [`Error::Unavailable`] if another fresh load was made within
+    /// [`BATCH_SOURCE_COOLDOWN`], so requests cannot hammer the batch source.
+    async fn load_fresh_batches(&self) -> Result<Vec<Batch>> {
+        {
+            let mut loaded_at = self.fresh_batches_at.lock().unwrap();
+            if let Some(loaded_at) = *loaded_at {
+                let next = loaded_at + BATCH_SOURCE_COOLDOWN;
+                let now = Instant::now();
+                if next > now {
+                    return Err(Error::Unavailable(next - now));
+                }
+            }
+            *loaded_at = Some(Instant::now());
+        }
+
+        let (batches, _) = self.read_batch_source().await?;
+        Ok(batches)
+    }
+
+    /// read the batch source, taking off the consumption not reflected by it
+    ///
+    /// Waits out a cooldown after the batch source was unavailable.
+    async fn read_batch_source(&self) -> Result<(Vec<Batch>, Vec<BatchError>)> {
+        if let Some(retry_at) = *self.batches_retry_at.lock().unwrap() {
+            let now = Instant::now();
+            if retry_at > now {
+                return Err(Error::Unavailable(retry_at - now));
+            }
+        }
+
+        match load_batches().await {
+            Ok((mut batches, skipped)) => {
+                self.consumption
+                    .lock()
+                    .await
+                    .apply(&mut batches, Batch::source_modified())
+                    .await;
+                Ok((batches, skipped))
+            }
+            Err(e) => {
+                if let Error::Unavailable(cooldown) = e {
+                    *self.batches_retry_at.lock().unwrap() = Some(Instant::now() + cooldown);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Database pool of the plant selected by the `X-Plant` header
+///
+/// Requests without the header use the default plant. `GET` requests use the
+/// plant's read replica while it is healthy, everything else the primary.
+struct PlantDb {
+    plant: String,
+    pool: db::DbPool,
+    /// primary pool, used if the read replica fails to connect
+    primary: db::DbPool,
+    replica: Option<Arc<db::Replica>>,
+    /// maximum number of connections of the pool
+    max_size: u32,
+}
+
+impl PlantDb {
+    /// get a connection of the plant's database
+    ///
+    /// If the read replica fails to give one, it is marked down and a
+    /// connection of the primary is used instead.
+    async fn conn(&self) -> Result<db::SqlConn<'static>> {
+        let error = match self.pool.get_owned().await {
+            Ok(conn) => return Ok(conn),
+            Err(e) => e,
+        };
+
+        match &self.replica {
+            Some(replica) => {
+                log::debug!("Read replica failed to connect: {:?}", error);
+                replica.failed(&self.plant);
+                Ok(self.primary.get_owned().await?)
+            }
+            None => Err(error.into()),
+        }
+    }
+
+    /// nest lookups run at once by `/nests` and `/snapshot`, leaving the rest
+    /// of the pool for other requests
+    fn nest_lookup_concurrency(&self) -> usize {
+        (self.max_size as usize / 2).max(1)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for PlantDb {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self> {
+        let plant = parts
+            .headers
+            .get(db::PLANT_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| Error::BadRequest(format!("Invalid {} header", db::PLANT_HEADER)))
+            })
+            .transpose()?;
+        let (plant, pool) = state.plants.get(plant)?;
+
+        let read_only = matches!(parts.method, Method::GET | Method::HEAD);
+        let replica = read_only
+            .then(|| state.plants.replica(plant))
+            .flatten()
+            .cloned();
+
+        Ok(Self {
+            plant: plant.into(),
+            pool: replica
+                .as_ref()
+                .map_or(pool, |replica| replica.pool())
+                .clone(),
+            primary: pool.clone(),
+            replica,
+            max_size: state.plants.max_size(plant),
+        })
     }
+}
 
-    (StatusCode::CREATED, Json(Value::Null))
+/// Interval to check for background refreshes being enabled while they are not
+const CACHE_REFRESH_IDLE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// periodically reload the batch and machine caches, at the configured interval
+///
+/// Requests keep being served from the previous caches if a refresh fails.
+async fn refresh_caches(state: Arc<AppState>) {
+    loop {
+        let interval = match state.config().cache_refresh {
+            Some(interval) => interval.to_std().unwrap_or(CACHE_REFRESH_IDLE),
+            None => {
+                sleep(CACHE_REFRESH_IDLE).await;
+                continue;
+            }
+        };
+        sleep(interval).await;
+
+        match state.refresh_batches().await {
+            Ok(count) => log::info!("batch cache refreshed with {} batches", count),
+            Err(e) => {
+                log::error!("Failed to refresh batch cache, serving previous batches");
+                log::error!("{:#?}", e);
+            }
+        }
+        state.refresh_machines().await;
+    }
+}
+
+/// periodically check the read replicas, see [`db::Plants::check_replicas`]
+async fn check_replicas(state: Arc<AppState>) {
+    loop {
+        state.plants.check_replicas().await;
+        sleep(db::REPLICA_CHECK_INTERVAL).await;
+    }
+}
+
+/// post buffered SimTrans completions whenever the buffer fills or its interval is up
+///
+/// Completions that failed to post, such as the ones held while SimTrans was
+/// paused, are retried on every interval.
+async fn flush_simtrans(state: Arc<AppState>) {
+    loop {
+        let interval = state.config().simtrans_buffer_interval;
+        state
+            .simtrans_buffer
+            .wait(interval.to_std().unwrap_or_default())
+            .await;
+        // buffered completions are held while SimTrans is paused, like queued ones
+        if !state.simtrans_enabled.load(Ordering::Acquire) {
+            continue;
+        }
+
+        let config = state.config();
+        let flushed = state
+            .simtrans_buffer
+            .flush(
+                &state.plants,
+                config.simtrans_district,
+                config.simtrans_proc.as_deref(),
+            )
+            .await;
+        if flushed.posted > 0 {
+            log::debug!("Posted {} buffered SimTrans completions", flushed.posted);
+        }
+    }
+}
+
+/// Interval to check for failed NC moves that are due to be retried
+const NC_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// retry failed NC moves in the background, see [`PendingMoves::retry_due`]
+async fn retry_nc_moves(state: Arc<AppState>) {
+    loop {
+        sleep(NC_RETRY_INTERVAL).await;
+
+        let moved = state.pending_nc_moves.lock().await.retry_due().await;
+        if moved > 0 {
+            log::info!("Retried {} pending NC moves", moved);
+        }
+    }
+}
+
+/// load batches from the data source, with the malformed records that were skipped
+async fn load_batches() -> Result<(Vec<Batch>, Vec<BatchError>)> {
+    let (batches, skipped) = Batch::get_batches()?;
+    if !skipped.is_empty() {
+        log::warn!("Skipped {} malformed batch records", skipped.len());
+    }
+
+    Ok((batches, skipped))
+}
+
+/// check every plant's database has the tables and columns the server uses,
+/// panicking if any are missing
+async fn check_schemas(state: &AppState) {
+    let verbose = std::env::var("SN_SCHEMA_CHECK_VERBOSE").is_ok_and(|v| v == "1" || v == "true");
+    for (plant, pool) in state.plants.iter() {
+        let missing = db::schema::check_schema(pool, verbose)
+            .await
+            .expect("failed to connect for schema check");
+        if !missing.is_empty() {
+            log::error!("Database of plant {} is missing {:?}", plant, missing);
+            panic!(
+                "database of plant {} is missing required tables or columns: {}",
+                plant,
+                missing.join("; ")
+            );
+        }
+        log::info!("schema check of plant {} passed", plant);
+    }
+}
+
+/// router serving the routes of [`ROUTES`], requiring the write key where listed
+///
+/// Routes are only served if they are listed, so the list is the reference
+/// for the auth contract of the API. `/health` is not routed here, as it is
+/// added after the concurrency limit, and `/mock` fixtures are nested by the
+/// mock feature. `/admin/queries` is only routed if `debug`.
+fn router(write_key: WriteKey, debug: bool) -> Router<Arc<AppState>> {
+    let mut router = Router::new();
+    for route in ROUTES {
+        match (route.method, route.path) {
+            // answered by the GET handler of the path
+            ("HEAD", _) => continue,
+            (_, "/health") => continue,
+            (_, "/admin/queries") if !debug => continue,
+            (_, path) if path.starts_with("/mock/") => continue,
+            _ => (),
+        }
+
+        let mut handler = route_handler(route.method, route.path)
+            .unwrap_or_else(|| panic!("route {} {} has no handler", route.method, route.path));
+        if route.auth == AuthLevel::Write {
+            handler = handler.route_layer(middleware::from_fn_with_state(
+                write_key.clone(),
+                require_write_key,
+            ));
+        }
+
+        // handlers of other methods of the path are merged with this one
+        router = router.route(route.path, handler);
+    }
+
+    router
+}
+
+/// handler of a route of [`ROUTES`], by its method and path
+fn route_handler(method: &str, path: &str) -> Option<MethodRouter<Arc<AppState>>> {
+    let handler = match (method, path) {
+        ("GET", "/") => get(|| async { "root request not implemented yet" }),
+        ("GET", "/ready") => get(get_ready),
+        ("GET", "/routes") => get(get_routes),
+        ("GET", "/machines") => get(get_machines),
+        ("POST", "/machines/:machine/status") => post(set_machine_status),
+        ("GET", "/machines/:machine/next") => get(get_next_program),
+        ("GET", "/batches") => get(get_batches),
+        ("GET", "/batches.csv") => get(get_batches_csv),
+        ("GET", "/batches/reservations") => get(get_reservations),
+        ("GET", "/batches/status") => get(get_batches_status),
+        // bulk endpoints take gzip request bodies, other encodings are rejected with a 415
+        ("POST", "/batches/reserve-bulk") => {
+            post(reserve_batches).layer(RequestDecompressionLayer::new())
+        }
+        ("GET", "/materials") => get(get_materials),
+        ("GET", "/materials/:material/demand") => get(get_material_demand),
+        ("GET", "/batches/:program") => get(get_batches_for_program),
+        ("POST", "/batches/:batch/reservation") => post(reserve_batch),
+        ("DELETE", "/batches/:batch/reservation") => delete(release_reservation),
+        ("GET", "/simtrans/transactions") => get(get_simtrans_transactions),
+        ("GET", "/reports/throughput") => get(get_throughput),
+        ("GET", "/programs/unmatched") => get(get_unmatched_programs),
+        ("GET", "/programs/by-state/:state") => get(get_programs_by_state),
+        ("POST", "/programs/cancel") => {
+            post(cancel_programs).layer(RequestDecompressionLayer::new())
+        }
+        ("GET", "/:machine") => get(get_programs),
+        ("GET", "/:machine/by-sheet") => get(get_programs_by_sheet),
+        ("GET", "/nest/:nest") => get(get_nest),
+        ("POST", "/nest/:nest") => post(update_program),
+        ("PATCH", "/nest/:nest") => patch(patch_program),
+        ("POST", "/nests") => post(get_nests).layer(RequestDecompressionLayer::new()),
+        ("GET", "/snapshot") => get(get_snapshot),
+        ("GET", "/snapshot/diff") => get(get_snapshot_diff),
+        ("GET", "/nest/:nest/status") => get(get_nest_status),
+        ("GET", "/nest/:nest/timing") => get(get_nest_timing),
+        ("GET", "/nest/:nest/siblings") => get(get_nest_siblings),
+        ("GET", "/nest/:nest/position") => get(get_nest_position),
+        ("GET", "/nest/:nest/estimate") => get(get_nest_estimate),
+        ("GET", "/nest/:nest/bbox") => get(get_nest_bbox),
+        ("GET", "/nest/:nest/remnant") => get(get_nest_remnant),
+        ("GET", "/nest/:nest/utilization") => get(get_nest_utilization),
+        ("GET", "/nest/:nest/related-by-part") => get(get_related_by_part),
+        ("POST", "/nest/:nest/reprint") => post(reprint_nest),
+        ("GET", "/nest/:nest/validate") => get(get_nest_validation),
+        ("POST", "/nest/:nest/machine") => post(assign_machine),
+        ("GET", "/nest/:nest/batch/:batch/check") => get(check_nest_batch),
+        ("POST", "/nest/:nest/hide") => post(hide_nest),
+        ("POST", "/nest/:nest/unhide") => post(unhide_nest),
+        ("GET", "/nest/:nest/priority") => get(get_nest_priority),
+        ("POST", "/nest/:nest/priority") => post(set_nest_priority),
+        ("GET", "/workorders/:wo/programs") => get(get_work_order_programs),
+        ("GET", "/events") => get(stream_events),
+        ("GET", "/ws/:machine") => get(stream_machine_events),
+        ("GET", "/feedback") => get(get_feedback),
+        ("GET", "/feedback/by-part") => get(get_feedback_by_part),
+        ("POST", "/feedback/:id/resolve") => post(resolve_feedback),
+        ("GET", "/admin/pool") => get(get_pool_state),
+        ("POST", "/admin/simtrans/pause") => post(pause_simtrans),
+        ("POST", "/admin/simtrans/resume") => post(resume_simtrans),
+        ("POST", "/admin/reload-config") => post(reload_config),
+        ("GET", "/admin/config") => get(get_config),
+        ("GET", "/admin/nc/pending") => get(get_pending_nc_moves),
+        ("GET", "/admin/logs") => get(get_logs),
+        ("GET", "/admin/logs/ws") => get(stream_logs),
+        ("GET", "/admin/queries") => get(get_queries),
+        _ => return None,
+    };
+
+    Some(handler)
+}
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), std::io::Error> {
+    let log_stream = LogStream::new();
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "[{} {} {}] {}",
+                humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level(log::LevelFilter::Error)
+        .level_for("sigmanest_interface", log::LevelFilter::Trace)
+        .chain(
+            fern::Dispatch::new()
+                .level(log::LevelFilter::Debug)
+                .chain(std::io::stdout()),
+        )
+        .chain(
+            fern::Dispatch::new().level(log::LevelFilter::Trace).chain(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .truncate(true)
+                    .write(true)
+                    .open(logs::LOG_FILE)?,
+            ),
+        )
+        .chain(
+            fern::Dispatch::new()
+                .level(log::LevelFilter::Trace)
+                .chain(log_stream.output()),
+        )
+        .apply()
+        .expect("failed to init logging");
+
+    let config = Config::load().expect("failed to load config");
+    config.apply();
+
+    let state = Arc::new(
+        AppState::new(config, log_stream)
+            .await
+            .expect("failed to configure databases"),
+    );
+
+    // fail fast if a database is missing tables or columns, rather than on every request
+    // the mock feature serves fixtures, so it starts without a database
+    if !cfg!(feature = "mock") {
+        check_schemas(&state).await;
+    }
+
+    // start from the batches saved by the last run, if they are fresh enough
+    if let Some((saved_at, batches)) = cache::load_batches(state.config().cache_max_age).await {
+        log::info!("loaded {} batches from cache file", batches.len());
+        *state.batches.lock().await = Some(batches);
+        *state.batches_loaded_at.lock().unwrap() = Some(saved_at);
+        state.ready.store(true, Ordering::Release);
+    }
+
+    // warm up the batch cache so the first request does not pay for the load
+    let warm_up = Arc::clone(&state);
+    tokio::spawn(async move {
+        match warm_up.refresh_batches().await {
+            Ok(count) => log::info!("batch cache warmed up with {} batches", count),
+            Err(e) => {
+                log::error!("Failed to warm up batch cache, will retry on request");
+                log::error!("{:#?}", e);
+            }
+        }
+    });
+
+    tokio::spawn(refresh_caches(Arc::clone(&state)));
+    tokio::spawn(retry_nc_moves(Arc::clone(&state)));
+    tokio::spawn(check_replicas(Arc::clone(&state)));
+    tokio::spawn(flush_simtrans(Arc::clone(&state)));
+
+    // debug endpoints are only served if env `SN_DEBUG` is set
+    let debug = std::env::var("SN_DEBUG").is_ok_and(|debug| debug == "1" || debug == "true");
+    if debug {
+        log::warn!("SN_DEBUG is set, serving debug endpoints");
+    }
+    let app = router(WriteKey::from_env(), debug);
+
+    // fixtures for developing the UI offline, never built into releases
+    #[cfg(feature = "mock")]
+    let app = {
+        log::warn!("Built with the mock feature, serving fixtures under /mock");
+        app.nest("/mock", sigmanest_interface::mock::router())
+    };
+
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            ConcurrencyLimit::from_env(state.plants.total_max_size()),
+            shed_load,
+        ))
+        // added after the limit so health checks still answer when overloaded
+        .route("/health", get(get_health))
+        .with_state(Arc::clone(&state));
+
+    // wraps the router so rejections of unmatched routes also become problems
+    let app = middleware::from_fn(problem_responses).layer(app);
+    let app = middleware::from_fn_with_state(PrettyJson::from_env(), pretty_json).layer(app);
+
+    // paths are normalized before the router matches them
+    let app =
+        middleware::from_fn_with_state(PathNormalization::from_env(), normalize_path).layer(app);
+    let app = NormalizePathLayer::trim_trailing_slash().layer(app);
+
+    // run our app with hyper, listening globally on port 3080
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3080").await?;
+    let shutdown = CancellationToken::new();
+    let serve = axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
+        .with_graceful_shutdown({
+            let shutdown = shutdown.clone();
+            async move { shutdown.cancelled().await }
+        })
+        .into_future();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    // websockets and event streams stay open until clients leave, so they are not waited for
+    let grace = async {
+        shutdown.cancelled().await;
+        sleep(SHUTDOWN_GRACE).await;
+    };
+    tokio::select! {
+        served = serve => served?,
+        _ = grace => log::warn!("Connections still open after {:?}, shutting down", SHUTDOWN_GRACE),
+    }
+
+    // buffered completions are posted before exiting, or saved for the next
+    // run while SimTrans is paused
+    match state.simtrans_enabled.load(Ordering::Acquire) {
+        true => {
+            let config = state.config();
+            let flushed = state
+                .simtrans_buffer
+                .flush(
+                    &state.plants,
+                    config.simtrans_district,
+                    config.simtrans_proc.as_deref(),
+                )
+                .await;
+            log::info!(
+                "Posted {} buffered SimTrans completions at shutdown, {} left",
+                flushed.posted,
+                flushed.pending
+            );
+        }
+        false => state.simtrans_buffer.save().await,
+    }
+
+    Ok(())
+}
+
+/// Longest open requests are waited for at shutdown
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// wait for ctrl-c, or `SIGTERM` on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => log::info!("Received ctrl-c, shutting down"),
+        _ = terminate => log::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+async fn get_health() -> (StatusCode, Json<Value>) {
+    (StatusCode::OK, Json(json!({ "status": "ok" })))
+}
+
+async fn get_ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    let ready = state.ready.load(Ordering::Acquire);
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, Json(json!({ "ready": ready })))
+}
+
+async fn get_routes() -> (StatusCode, Json<&'static [RouteInfo]>) {
+    log::debug!("Requested routes list");
+
+    (StatusCode::OK, Json(ROUTES))
+}
+
+async fn get_machines(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Query(params): Query<MachineListParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested machines list");
+
+    let config = state.config();
+    let mut machines = state.machines(&db).await?;
+    machines.retain(|machine| config.is_managed(machine));
+    if params.active {
+        let statuses = state.machine_statuses.lock().await;
+        machines.retain(|machine| !statuses.is_offline(&db.plant, machine));
+    }
+
+    Ok((StatusCode::OK, Json(json!(machines))))
+}
+
+async fn set_machine_status(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(machine): Path<MachineName>,
+    extract::Json(params): extract::Json<MachineStatusParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!(
+        "Requested machine {} be set {:?} by {}",
+        machine,
+        params.status,
+        operator
+    );
+    state.config().check_managed(machine.as_str())?;
+    params.validate()?;
+
+    if !state
+        .machines(&db)
+        .await?
+        .iter()
+        .any(|m| m == machine.as_str())
+    {
+        return Err(Error::NotFound(format!("Machine {} not found", machine)));
+    }
+
+    let mut statuses = state.machine_statuses.lock().await;
+    let downtime = match params.status {
+        MachineStatus::Offline => {
+            let downtime = statuses.set_offline(
+                &db.plant,
+                &machine,
+                params.reason.as_deref(),
+                operator.as_str(),
+            );
+            log::info!(
+                "Machine {} taken offline by {}: {:?}",
+                machine,
+                operator,
+                params.reason
+            );
+            Some(downtime)
+        }
+        MachineStatus::Online => {
+            if statuses.set_online(&db.plant, &machine).is_some() {
+                log::info!("Machine {} back online by {}", machine, operator);
+            }
+            None
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "machine": machine,
+            "status": params.status,
+            "downtime": downtime,
+        })),
+    ))
+}
+
+/// also serves `HEAD /batches`, as axum routes HEAD requests to GET handlers
+async fn get_batches(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response> {
+    // spreadsheets ask for CSV, everything else gets JSON
+    let wants_csv = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(batch::accepts_csv);
+
+    batches_response(&state, method, wants_csv).await
+}
+
+/// `GET /batches` as CSV, for spreadsheets that cannot set an `Accept` header
+async fn get_batches_csv(State(state): State<Arc<AppState>>, method: Method) -> Result<Response> {
+    batches_response(&state, method, true).await
+}
+
+async fn batches_response(state: &Arc<AppState>, method: Method, csv: bool) -> Result<Response> {
+    log::debug!("Requested batches list");
+
+    let batches = match state.batches().await {
+        Ok(batches) => batches,
+        // batches are only loaded on request while none have ever been loaded
+        Err(e) => {
+            log::error!("No batches loaded");
+            log::error!("{:#?}", e);
+
+            // the list has the same shape in either mode, only the header tells them apart
+            let headers = [(BATCHES_LOADED_HEADER, "false")];
+            return match state.config().batches_empty_mode {
+                BatchesEmptyMode::Strict => match e {
+                    Error::Unavailable(_) => Err(e),
+                    _ => Err(Error::Unavailable(BATCH_SOURCE_COOLDOWN)),
+                },
+                BatchesEmptyMode::Lenient if method == Method::HEAD => {
+                    Ok((StatusCode::OK, headers).into_response())
+                }
+                BatchesEmptyMode::Lenient if csv => Ok((
+                    StatusCode::OK,
+                    headers,
+                    [(header::CONTENT_TYPE, batch::CSV_CONTENT_TYPE)],
+                    Batch::to_csv(&[])?,
+                )
+                    .into_response()),
+                BatchesEmptyMode::Lenient => {
+                    Ok((StatusCode::OK, headers, Json(Vec::<Batch>::new())).into_response())
+                }
+            };
+        }
+    };
+
+    let skipped = state.batches_skipped.load(Ordering::Acquire);
+    let headers = [
+        (BATCHES_SKIPPED_HEADER, skipped.to_string()),
+        (BATCHES_LOADED_HEADER, "true".into()),
+    ];
+
+    // the body of a HEAD response is dropped, so the list is not copied and serialized
+    if method == Method::HEAD {
+        return Ok((StatusCode::OK, headers).into_response());
+    }
+
+    if csv {
+        return Ok((
+            StatusCode::OK,
+            headers,
+            [(header::CONTENT_TYPE, batch::CSV_CONTENT_TYPE)],
+            Batch::to_csv(&batches)?,
+        )
+            .into_response());
+    }
+
+    Ok((StatusCode::OK, headers, Json(batches.clone())).into_response())
+}
+
+async fn get_materials(
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Material>>)> {
+    log::debug!("Requested materials list");
+
+    let batches = state.batches().await?;
+    let materials = Material::from_batches(&batches);
+
+    Ok((StatusCode::OK, Json(materials)))
+}
+
+/// material masters contain `/`, which has to be percent encoded in the path
+async fn get_material_demand(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(material): Path<String>,
+) -> Result<(StatusCode, Json<MaterialDemand>)> {
+    log::debug!("Requested demand of material {}", material);
+
+    let mut conn = db.conn().await?;
+    let repeats = db::timed(
+        conn.breaker(),
+        Sheet::queued_repeats(&mut conn, &state.config().query_settings(), &material),
+    )
+    .await?;
+    // each repeat uses the sheets of one completion
+    let required = repeats.max(0) as u32 * SHEETS_PER_COMPLETION;
+
+    let batches = state.batches().await?;
+    let demand = MaterialDemand::new(&material, required, &batches);
+
+    Ok((StatusCode::OK, Json(demand)))
+}
+
+async fn get_batches_for_program(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+    Query(params): Query<BatchMatchParams>,
+) -> Result<Response> {
+    log::debug!("Requested batches list for program `{}`", program);
+
+    let state = Arc::clone(&state);
+
+    // a fresh load is only used for this request, the cache is left as it is
+    let fresh;
+    let cached;
+    let batches: &[Batch] = match params.fresh {
+        true => {
+            log::info!("Fresh batch load forced for program {}", program);
+            fresh = state.load_fresh_batches().await?;
+            &fresh
+        }
+        false => {
+            cached = state.batches().await?;
+            &cached
+        }
+    };
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+
+    let mm_batches: Vec<Batch> = batches
+        .iter()
+        .filter(|bat| bat.matches_sheet(&nest.sheet))
+        .cloned()
+        .collect();
+
+    let tolerance = match params.tolerance {
+        Some(tolerance) if tolerance < 0.0 => {
+            return Err(Error::BadRequest("`tolerance` cannot be negative".into()))
+        }
+        Some(tolerance) => tolerance,
+        None => return Ok((StatusCode::OK, Json(mm_batches)).into_response()),
+    };
+
+    // alternatives are only offered when the exact sheet is out
+    let matches: Vec<BatchMatch> = match mm_batches.is_empty() {
+        false => mm_batches
+            .into_iter()
+            .map(|batch| BatchMatch {
+                batch,
+                exact_match: true,
+            })
+            .collect(),
+        true => batches
+            .iter()
+            .filter(|bat| bat.is_compatible(&nest.sheet, tolerance))
+            .map(|batch| BatchMatch {
+                batch: batch.clone(),
+                exact_match: false,
+            })
+            .collect(),
+    };
+
+    Ok((StatusCode::OK, Json(matches)).into_response())
+}
+
+async fn get_nest_siblings(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<Vec<Program>>)> {
+    log::debug!("Requested programs sharing a sheet with `{}`", program);
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+
+    let siblings = db::timed(
+        conn.breaker(),
+        Program::get_siblings(
+            &mut conn,
+            &state.config().query_settings(),
+            &program,
+            &nest.sheet.sheet_name,
+        ),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(siblings)))
+}
+
+/// get how fresh the batch cache is, without loading it
+///
+/// Batches are stale once older than the background refresh interval, or
+/// than the cache max age if they are not refreshed in the background.
+/// Batches that were never loaded are always stale.
+async fn get_batches_status(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    log::debug!("Requested batch cache status");
+
+    let config = state.config();
+    let ttl = config.cache_refresh.unwrap_or(config.cache_max_age);
+
+    let count = state.batches.lock().await.as_ref().map(Vec::len);
+    let loaded_at = *state.batches_loaded_at.lock().unwrap();
+    let age = loaded_at.map(|loaded_at| Utc::now() - loaded_at);
+    let fresh = count.is_some() && age.is_some_and(|age| age <= ttl);
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "loadedAt": loaded_at,
+            "ageSeconds": age.map(|age| age.num_seconds()),
+            "ttlSeconds": ttl.num_seconds(),
+            "count": count.unwrap_or_default(),
+            "stale": !fresh,
+        })),
+    )
+}
+
+async fn get_reservations(
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Reservation>>)> {
+    log::debug!("Requested batch reservations");
+
+    let state = Arc::clone(&state);
+    let reservations = state.reservations.lock().await.active();
+
+    Ok((StatusCode::OK, Json(reservations)))
+}
+
+async fn reserve_batch(
+    State(state): State<Arc<AppState>>,
+    Path(batch): Path<String>,
+    operator: Operator,
+    extract::Json(params): extract::Json<ReservationParams>,
+) -> Result<(StatusCode, Json<Reservation>)> {
+    log::debug!(
+        "Requested reservation of batch {} for {} by {}",
+        batch,
+        params.holder,
+        operator
+    );
+
+    let ttl = reservation_ttl(params.ttl_seconds, state.config().reservation_ttl)?;
+    let batches = state.batches().await?;
+
+    if !batches.iter().any(|bat| bat.id == batch) {
+        return Err(Error::NotFound(format!("Batch {} not found", batch)));
+    }
+
+    let reservation = state
+        .reservations
+        .lock()
+        .await
+        .reserve(&batch, &params.holder, ttl)?;
+
+    log::info!(
+        "Batch {} reserved for {} until {} by {}",
+        batch,
+        reservation.holder,
+        reservation.expires_at,
+        operator
+    );
+    Ok((StatusCode::CREATED, Json(reservation)))
+}
+
+async fn reserve_batches(
+    State(state): State<Arc<AppState>>,
+    operator: Operator,
+    extract::Json(params): extract::Json<BulkReservationParams>,
+) -> Result<(StatusCode, Json<Vec<Reservation>>)> {
+    log::debug!(
+        "Requested reservation of {} batches for {} by {}",
+        params.batches.len(),
+        params.holder,
+        operator
+    );
+
+    if params.batches.is_empty() {
+        return Err(Error::BadRequest("No batches to reserve".into()));
+    }
+
+    let ttl = reservation_ttl(params.ttl_seconds, state.config().reservation_ttl)?;
+    let batches = state.batches().await?;
+    let unknown: Vec<&str> = params
+        .batches
+        .iter()
+        .filter(|batch| !batches.iter().any(|bat| &bat.id == *batch))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(Error::NotFound(format!(
+            "Batches {} not found",
+            unknown.join(", ")
+        )));
+    }
+
+    let reservations =
+        state
+            .reservations
+            .lock()
+            .await
+            .reserve_all(&params.batches, &params.holder, ttl)?;
+
+    log::info!(
+        "Batches {} reserved for {} by {}",
+        params.batches.join(", "),
+        params.holder,
+        operator
+    );
+    Ok((StatusCode::CREATED, Json(reservations)))
+}
+
+async fn release_reservation(
+    State(state): State<Arc<AppState>>,
+    Path(batch): Path<String>,
+    operator: Operator,
+) -> Result<(StatusCode, Json<Reservation>)> {
+    log::debug!("Requested release of batch {} by {}", batch, operator);
+
+    let state = Arc::clone(&state);
+    let reservation = state.reservations.lock().await.release(&batch);
+
+    match reservation {
+        Some(reservation) => {
+            log::info!(
+                "Reservation of batch {} by {} cleared by {}",
+                batch,
+                reservation.holder,
+                operator
+            );
+            Ok((StatusCode::OK, Json(reservation)))
+        }
+        None => Err(Error::NotFound(format!("Batch {} is not reserved", batch))),
+    }
+}
+
+async fn get_feedback(
+    db: PlantDb,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response> {
+    // `machine` may be repeated to filter on multiple machines
+    let machines: Vec<MachineName> = params
+        .iter()
+        .filter(|(key, _)| key == "machine")
+        .map(|(_, machine)| machine.parse())
+        .collect::<Result<_>>()?;
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let query = FeedbackQuery {
+        machines,
+        resolution: param("status").map(str::parse).transpose()?,
+        after: param("after").map(str::parse).transpose()?,
+        from_id: param("from_id")
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| Error::BadRequest(format!("Invalid from_id `{}`", id)))
+            })
+            .transpose()?,
+        limit: param("limit")
+            .map(|limit| {
+                limit
+                    .parse()
+                    .map_err(|_| Error::BadRequest(format!("Invalid limit `{}`", limit)))
+            })
+            .transpose()?,
+    };
+    log::debug!("Requested feedback {:?}", query);
+
+    let mut conn = db.conn().await?;
+    // without a cursor or limit, all feedback is returned as a plain list
+    if query.is_paged() {
+        let page = db::timed(
+            conn.breaker(),
+            export_feedback_page(&mut conn, db.pool.clone(), &query),
+        )
+        .await?;
+        return Ok((StatusCode::OK, Json(page)).into_response());
+    }
+
+    let feedback = db::timed(
+        conn.breaker(),
+        export_feedback(&mut conn, db.pool.clone(), &query),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(feedback)).into_response())
+}
+
+async fn get_feedback_by_part(
+    db: PlantDb,
+    Query(params): Query<FeedbackByPartParams>,
+) -> Result<(StatusCode, Json<Vec<PartFeedbackCount>>)> {
+    log::debug!("Requested feedback counts by part {:?}", params);
+
+    let mut conn = db.conn().await?;
+    let counts = db::timed(
+        conn.breaker(),
+        export_feedback_by_part(&mut conn, params.by_type),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(counts)))
+}
+
+async fn resolve_feedback(
+    db: PlantDb,
+    operator: Operator,
+    Path(id): Path<i32>,
+    extract::Json(params): extract::Json<ResolveParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested feedback {} be marked {:?}", id, params.status);
+
+    let mut conn = db.conn().await?;
+    db::timed(
+        conn.breaker(),
+        FeedbackEntry::resolve(&mut conn, id, params.status),
+    )
+    .await?;
+    log::info!(
+        "Feedback {} marked {} by {}",
+        id,
+        params.status.as_str(),
+        operator
+    );
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "archivePacketId": id, "resolution": params.status })),
+    ))
+}
+
+async fn get_programs(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(machine): Path<MachineName>,
+    Query(params): Query<ProgramListParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested programs for machine {}", machine);
+    state.config().check_managed(machine.as_str())?;
+
+    let max_programs = state.config().max_programs;
+
+    // fetch one more than the cap to know if the list was truncated
+    let mut conn = db.conn().await?;
+    let mut programs = db::timed(
+        conn.breaker(),
+        MachineProgram::get_by_machine(
+            &mut conn,
+            &machine,
+            max_programs + 1,
+            state.config().complete_grace,
+            &state.config().query_settings(),
+            params.include_hidden,
+            matches!(params.sort, Some(ProgramSort::DueDate)),
+        ),
+    )
+    .await?;
+
+    let duplicated = MachineProgram::dedup_by_program(&mut programs);
+    if !duplicated.is_empty() {
+        log::warn!(
+            "Collapsed duplicate listings of programs {} for machine {}",
+            duplicated.join(", "),
+            machine
+        );
+    }
+
+    let truncated = programs.len() > max_programs;
+    if truncated {
+        log::warn!(
+            "Programs for machine {} truncated to {}",
+            machine,
+            max_programs
+        );
+        programs.truncate(max_programs);
+    }
+
+    let downtime = state
+        .machine_statuses
+        .lock()
+        .await
+        .downtime(&db.plant, &machine)
+        .cloned();
+    let status = match downtime {
+        Some(_) => MachineStatus::Offline,
+        None => MachineStatus::Online,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "machine": machine,
+            "programs": programs,
+            "activity": MachineActivity::of_queue(&programs),
+            "truncated": truncated,
+            "status": status,
+            "downtime": downtime,
+        })),
+    ))
+}
+
+/// get the programs of a machine grouped by their sheet
+///
+/// Groups are in queue order of their first program, and the programs of a
+/// group keep their queue order.
+async fn get_programs_by_sheet(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(machine): Path<MachineName>,
+) -> Result<(StatusCode, Json<Vec<SheetGroup>>)> {
+    log::debug!("Requested programs by sheet for machine {}", machine);
+    state.config().check_managed(machine.as_str())?;
+
+    let mut conn = db.conn().await?;
+    let programs = db::timed(
+        conn.breaker(),
+        MachineProgram::get_by_machine(
+            &mut conn,
+            &machine,
+            state.config().max_programs,
+            state.config().complete_grace,
+            &state.config().query_settings(),
+            false,
+            false,
+        ),
+    )
+    .await?;
+
+    // sheets of all queued programs come from one query, rather than one per program
+    let mut sheets: HashMap<String, Sheet> = db::timed(
+        conn.breaker(),
+        QueuedProgram::get_all(&mut conn, &state.config().query_settings()),
+    )
+    .await?
+    .into_iter()
+    .map(|prg| (prg.program_name, prg.sheet))
+    .collect();
+
+    let mut groups: Vec<SheetGroup> = Vec::new();
+    for program in programs {
+        let sheet = sheets.remove(&program.program);
+        let name = sheet.as_ref().map(|sheet| sheet.sheet_name.clone());
+        match groups
+            .iter_mut()
+            .find(|group| group.sheet.as_ref().map(|sheet| &sheet.sheet_name) == name.as_ref())
+        {
+            Some(group) => group.programs.push(program),
+            None => groups.push(SheetGroup {
+                sheet,
+                programs: vec![program],
+            }),
+        }
+    }
+
+    Ok((StatusCode::OK, Json(groups)))
+}
+
+/// get the first program queued on a machine that can be run now
+///
+/// Programs are taken in queue order, and can be run if a batch that is not
+/// reserved has sheets for them and their NC file is in the machine's
+/// directory. If no NC directory is configured, NC files are not checked.
+async fn get_next_program(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(machine): Path<MachineName>,
+) -> Result<Response> {
+    log::debug!("Requested next program for machine {}", machine);
+    state.config().check_managed(machine.as_str())?;
+
+    if state
+        .machine_statuses
+        .lock()
+        .await
+        .is_offline(&db.plant, machine.as_str())
+    {
+        log::debug!("Machine {} is offline, nothing to run", machine);
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let (programs, sheets) = {
+        let mut conn = db.conn().await?;
+        let programs = db::timed(
+            conn.breaker(),
+            MachineProgram::get_by_machine(
+                &mut conn,
+                &machine,
+                state.config().max_programs,
+                state.config().complete_grace,
+                &state.config().query_settings(),
+                false,
+                false,
+            ),
+        )
+        .await?;
+        let sheets: HashMap<String, Sheet> = db::timed(
+            conn.breaker(),
+            QueuedProgram::get_all(&mut conn, &state.config().query_settings()),
+        )
+        .await?
+        .into_iter()
+        .map(|prg| (prg.program_name, prg.sheet))
+        .collect();
+
+        (programs, sheets)
+    };
+
+    let reserved: Vec<String> = state
+        .reservations
+        .lock()
+        .await
+        .active()
+        .into_iter()
+        .map(|reservation| reservation.batch)
+        .collect();
+    let batches = state.batches().await?;
+
+    for program in programs.into_iter().filter(|prg| !prg.just_completed) {
+        let sheet = match sheets.get(&program.program) {
+            Some(sheet) => sheet,
+            None => continue,
+        };
+        let available: Vec<&Batch> = batches
+            .iter()
+            .filter(|bat| bat.qty > 0 && bat.matches_sheet(sheet) && !reserved.contains(&bat.id))
+            .collect();
+        if available.is_empty() {
+            continue;
+        }
+
+        if nc::has_nc_program(machine.as_str(), &program.program).await? == Some(false) {
+            log::debug!("Program {} has no NC file, skipping", program.program);
+            continue;
+        }
+
+        let next = SnapshotProgram {
+            program,
+            sheet: Some(sheet),
+            batches: available,
+        };
+        return Ok((StatusCode::OK, Json(next)).into_response());
+    }
+
+    log::debug!("Nothing runnable on machine {}", machine);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn get_snapshot(State(state): State<Arc<AppState>>, db: PlantDb) -> Result<Response> {
+    log::debug!("Requested queue snapshot");
+
+    let config = state.config();
+    let mut machines = state.machines(&db).await?;
+    machines.retain(|machine| config.is_managed(machine));
+    let max_programs = state.config().max_programs;
+    let complete_grace = state.config().complete_grace;
+    let query_settings = config.query_settings();
+
+    // sheets of all queued programs come from one query, rather than one per program
+    let sheets: HashMap<String, Sheet> = {
+        let mut conn = db.conn().await?;
+        db::timed(
+            conn.breaker(),
+            QueuedProgram::get_all(&mut conn, &query_settings),
+        )
+        .await?
+        .into_iter()
+        .map(|prg| (prg.program_name, prg.sheet))
+        .collect()
+    };
+
+    let permits = Arc::new(Semaphore::new(db.nest_lookup_concurrency()));
+    let mut lookups = JoinSet::new();
+    for machine in machines {
+        // a legacy machine name that does not parse leaves out that machine, not the snapshot
+        let name: MachineName = match machine.parse() {
+            Ok(name) => name,
+            Err(e) => {
+                log::warn!("Skipping machine {} in snapshot: {}", machine, e);
+                continue;
+            }
+        };
+        let pool = db.pool.clone();
+        let permits = Arc::clone(&permits);
+        let query_settings = query_settings.clone();
+        lookups.spawn(async move {
+            let _permit = permits.acquire_owned().await.unwrap();
+            let programs = async {
+                let mut conn = pool.get_owned().await?;
+                db::timed(
+                    conn.breaker(),
+                    MachineProgram::get_by_machine(
+                        &mut conn,
+                        &name,
+                        max_programs + 1,
+                        complete_grace,
+                        &query_settings,
+                        false,
+                        false,
+                    ),
+                )
+                .await
+            }
+            .await;
+
+            (machine, programs)
+        });
+    }
+
+    let mut programs = BTreeMap::new();
+    while let Some(lookup) = lookups.join_next().await {
+        let (machine, machine_programs) = lookup.expect("snapshot lookup task panicked");
+        programs.insert(machine, machine_programs?);
+    }
+
+    let batches = state.batches().await?;
+    let statuses = state.machine_statuses.lock().await;
+    let snapshot: Vec<SnapshotMachine> = programs
+        .into_iter()
+        .map(|(machine, mut programs)| {
+            let truncated = programs.len() > max_programs;
+            programs.truncate(max_programs);
+
+            SnapshotMachine {
+                status: match statuses.is_offline(&db.plant, &machine) {
+                    true => MachineStatus::Offline,
+                    false => MachineStatus::Online,
+                },
+                programs: programs
+                    .into_iter()
+                    .map(|program| {
+                        let sheet = sheets.get(&program.program);
+                        SnapshotProgram {
+                            batches: batches
+                                .iter()
+                                .filter(|bat| sheet.is_some_and(|sheet| bat.matches_sheet(sheet)))
+                                .collect(),
+                            program,
+                            sheet,
+                        }
+                    })
+                    .collect(),
+                machine,
+                truncated,
+            }
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "generatedAt": Local::now().naive_local(),
+            "plant": db.plant,
+            "machines": snapshot,
+        })),
+    )
+        .into_response())
+}
+
+async fn get_unmatched_programs(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+) -> Result<(StatusCode, Json<Vec<QueuedProgram>>)> {
+    log::debug!("Requested programs with no matching batch");
+
+    let state = Arc::clone(&state);
+
+    let mut conn = db.conn().await?;
+    let programs = db::timed(
+        conn.breaker(),
+        QueuedProgram::get_all(&mut conn, &state.config().query_settings()),
+    )
+    .await?;
+
+    let batches = state.batches().await?;
+    let unmatched = programs
+        .into_iter()
+        .filter(|prg| !batches.iter().any(|bat| bat.matches_sheet(&prg.sheet)))
+        .collect();
+
+    Ok((StatusCode::OK, Json(unmatched)))
+}
+
+async fn get_snapshot_diff(
+    db: PlantDb,
+    Query(params): Query<SnapshotDiffParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let to = params.to.unwrap_or_else(|| Local::now().naive_local());
+    log::debug!("Requested queue changes from {} to {}", params.from, to);
+
+    if to < params.from {
+        return Err(Error::BadRequest("`to` is before `from`".into()));
+    }
+
+    let mut conn = db.conn().await?;
+    let changes = db::timed(
+        conn.breaker(),
+        QueueChange::between(&mut conn, params.from, to),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "from": params.from,
+            "to": to,
+            "changes": changes,
+        })),
+    ))
+}
+
+async fn get_programs_by_state(
+    db: PlantDb,
+    Path(program_state): Path<String>,
+) -> Result<(StatusCode, Json<Vec<StateLogEntry>>)> {
+    log::debug!("Requested programs in state {}", program_state);
+
+    let program_state: ProgramState = program_state.parse().map_err(|_| Error::InvalidState)?;
+
+    let mut conn = db.conn().await?;
+    let programs = db::timed(
+        conn.breaker(),
+        StateLogEntry::all_in_state(&mut conn, program_state),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(programs)))
+}
+
+async fn get_nest(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    headers: HeaderMap,
+    Path(program): Path<String>,
+    Query(params): Query<NestParams>,
+) -> Result<Response> {
+    log::debug!("Requested program {} (repeat {:?})", program, params.repeat);
+
+    let fields = params
+        .fields
+        .as_deref()
+        .map(Nest::parse_fields)
+        .transpose()?;
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get_repeat(
+            &mut conn,
+            &state.config().query_settings(),
+            &program,
+            params.repeat,
+        ),
+    )
+    .await?;
+
+    log::debug!("Nest found");
+
+    let nest = match fields {
+        Some(fields) => nest.select_fields(&fields),
+        None => serde_json::to_value(nest).unwrap(),
+    };
+
+    // legacy consumers ask for XML, everything else gets JSON
+    let wants_xml = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(xml::accepts_xml);
+    if wants_xml {
+        let body = xml::to_xml("nest", nest)?;
+        return Ok((
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, xml::XML_CONTENT_TYPE)],
+            body,
+        )
+            .into_response());
+    }
+
+    Ok((StatusCode::OK, Json(nest)).into_response())
+}
+
+async fn get_nests(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    extract::Json(params): extract::Json<NestsParams>,
+) -> Result<(StatusCode, Json<BTreeMap<String, Option<Nest>>>)> {
+    log::debug!("Requested {} programs", params.programs.len());
+
+    if params.programs.len() > MAX_NESTS_PER_REQUEST {
+        return Err(Error::BadRequest(format!(
+            "At most {} programs may be requested at once",
+            MAX_NESTS_PER_REQUEST
+        )));
+    }
+
+    let query_settings = state.config().query_settings();
+    let permits = Arc::new(Semaphore::new(db.nest_lookup_concurrency()));
+    let mut lookups = JoinSet::new();
+    for program in params.programs {
+        let pool = db.pool.clone();
+        let permits = Arc::clone(&permits);
+        let query_settings = query_settings.clone();
+        lookups.spawn(async move {
+            let _permit = permits.acquire_owned().await.unwrap();
+            let nest = async {
+                let mut conn = pool.get_owned().await?;
+                db::timed(
+                    conn.breaker(),
+                    Nest::get(&mut conn, &query_settings, &program),
+                )
+                .await
+            }
+            .await;
+
+            (program, nest)
+        });
+    }
+
+    // a program that fails to load is returned as null instead of failing the request
+    let mut nests = BTreeMap::new();
+    while let Some(lookup) = lookups.join_next().await {
+        let (program, nest) = lookup.expect("nest lookup task panicked");
+        let nest = match nest {
+            Ok(nest) => Some(nest),
+            Err(Error::NotFound(_)) => None,
+            Err(e) => {
+                log::error!("Failed to load program {}", program);
+                log::error!("{:#?}", e);
+                None
+            }
+        };
+        nests.insert(program, nest);
+    }
+
+    Ok((StatusCode::OK, Json(nests)))
+}
+
+async fn get_nest_status(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<ProgramStatus>)> {
+    log::debug!("Requested status of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let status = db::timed(
+        conn.breaker(),
+        ProgramStatus::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(status)))
+}
+
+async fn reprint_nest(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<NestReport>)> {
+    log::debug!("Requested reprint of program {} by {}", program, operator);
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    let status = db::timed(
+        conn.breaker(),
+        ProgramStatus::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    log::info!("Program {} paperwork reprinted by {}", program, operator);
+
+    Ok((
+        StatusCode::OK,
+        Json(NestReport {
+            nest,
+            status,
+            requested_by: operator.0,
+            generated_at: Local::now().naive_local(),
+        }),
+    ))
+}
+
+async fn get_nest_position(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<QueuePosition>)> {
+    log::debug!("Requested queue position of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let position = db::timed(
+        conn.breaker(),
+        QueuePosition::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(position)))
+}
+
+async fn get_nest_estimate(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<QueueEstimate>)> {
+    log::debug!("Requested start estimate of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let estimate = db::timed(
+        conn.breaker(),
+        QueueEstimate::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(estimate)))
+}
+
+async fn get_nest_bbox(
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<BoundingBox>)> {
+    log::debug!("Requested bounding box of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let bbox = db::timed(conn.breaker(), BoundingBox::get(&mut conn, &program)).await?;
+
+    Ok((StatusCode::OK, Json(bbox)))
+}
+
+async fn get_nest_remnant(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<RemnantEstimate>)> {
+    log::debug!("Requested remnant of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    let sheet = db::timed(conn.breaker(), BoundingBox::get(&mut conn, &program)).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RemnantEstimate::new(sheet, &nest.parts, nest.remnants)),
+    ))
+}
+
+async fn get_nest_utilization(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<Utilization>)> {
+    log::debug!("Requested utilization of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    let sheet = db::timed(conn.breaker(), BoundingBox::get(&mut conn, &program)).await?;
+
+    Ok((StatusCode::OK, Json(Utilization::new(&sheet, &nest.parts))))
+}
+
+async fn get_related_by_part(
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<Vec<RelatedProgram>>)> {
+    log::debug!("Requested programs sharing parts with {}", program);
+
+    let mut conn = db.conn().await?;
+    let related = db::timed(
+        conn.breaker(),
+        RelatedProgram::get_by_part(&mut conn, &program),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(related)))
+}
+
+async fn get_work_order_programs(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(work_order): Path<String>,
+) -> Result<(StatusCode, Json<Vec<WorkOrderProgram>>)> {
+    log::debug!("Requested programs of work order {}", work_order);
+
+    let mut conn = db.conn().await?;
+    let programs = db::timed(
+        conn.breaker(),
+        WorkOrderProgram::get_by_work_order(
+            &mut conn,
+            &state.config().query_settings(),
+            &work_order,
+        ),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(programs)))
+}
+
+async fn get_nest_timing(
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<ProgramTiming>)> {
+    log::debug!("Requested timing of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let timing = db::timed(conn.breaker(), ProgramTiming::get(&mut conn, &program)).await?;
+
+    Ok((StatusCode::OK, Json(timing)))
+}
+
+async fn get_nest_validation(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(program): Path<String>,
+    Query(params): Query<ValidateParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested completion checks of program {}", program);
+
+    let state = Arc::clone(&state);
+    let mut conn = db.conn().await?;
+    let status = db::timed(
+        conn.breaker(),
+        ProgramStatus::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    drop(conn);
+
+    // the same checks enforced by `transition_program` for `Complete`
+    let batch = params.batch.or(status.batch);
+    let transition = validate_transition(&program, status.current_state, ProgramState::Complete);
+    let batch = validate_batch(&state, &db, &program, batch.as_deref()).await;
+
+    let nc = match nc::has_nc_program(&nest.program.machine_name, &program).await {
+        Ok(Some(true)) | Ok(None) => Ok(()),
+        Ok(Some(false)) => Err(Error::NotFound(format!(
+            "No NC file for program {} on machine {}",
+            program, nest.program.machine_name
+        ))),
+        Err(e) => Err(e),
+    };
+
+    let simtrans = match state.simtrans_enabled.load(Ordering::Acquire) {
+        true => Ok(()),
+        false => Err(Error::Conflict(
+            "SimTrans is paused, completion will be queued".into(),
+        )),
+    };
+
+    let checks = [
+        CompletionCheck::new("state", transition),
+        CompletionCheck::new("batch", batch),
+        CompletionCheck::new("nc", nc),
+        CompletionCheck::new("simtrans", simtrans),
+    ];
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "program": program,
+            "canComplete": checks.iter().all(|check| check.ok),
+            "checks": checks,
+        })),
+    ))
+}
+
+async fn get_nest_priority(
+    db: PlantDb,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<ProgramPriority>)> {
+    log::debug!("Requested priority of program {}", program);
+
+    let mut conn = db.conn().await?;
+    let priority = db::timed(conn.breaker(), ProgramPriority::get(&mut conn, &program)).await?;
+
+    Ok((StatusCode::OK, Json(priority)))
+}
+
+async fn set_nest_priority(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+    extract::Json(params): extract::Json<PriorityParams>,
+) -> Result<(StatusCode, Json<ProgramPriority>)> {
+    log::debug!(
+        "Requested program {} be given priority {}",
+        program,
+        params.priority
+    );
+    validate_managed_program(&state, &db, &program).await?;
+
+    let mut conn = db.conn().await?;
+    let priority = db::timed(
+        conn.breaker(),
+        ProgramPriority::set(&mut conn, &program, params.priority, operator.as_str()),
+    )
+    .await?;
+    log::info!(
+        "Program {} given priority {} by {}",
+        program,
+        params.priority,
+        operator
+    );
+
+    Ok((StatusCode::OK, Json(priority)))
+}
+
+/// check if a batch can be used to cut a program, and why not if it cannot
+async fn check_nest_batch(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path((program, batch)): Path<(String, String)>,
+    Query(params): Query<BatchMatchParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested check of batch {} for program {}", batch, program);
+
+    if params.tolerance.is_some_and(|tolerance| tolerance < 0.0) {
+        return Err(Error::BadRequest("`tolerance` cannot be negative".into()));
+    }
+
+    let batch = state
+        .batches()
+        .await?
+        .iter()
+        .find(|bat| bat.id == batch)
+        .cloned()
+        .ok_or_else(|| Error::NotFound(format!("Batch {} not found", batch)))?;
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+
+    let reasons = batch.mismatches(&nest.sheet, params.tolerance);
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "compatible": reasons.is_empty(),
+            "exactMatch": batch.matches_sheet(&nest.sheet),
+            "reasons": reasons,
+        })),
+    ))
+}
+
+async fn hide_nest(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<HiddenProgram>)> {
+    log::debug!("Requested program {} be hidden", program);
+    validate_managed_program(&state, &db, &program).await?;
+
+    let mut conn = db.conn().await?;
+    let hidden = db::timed(
+        conn.breaker(),
+        HiddenProgram::hide(&mut conn, &program, operator.as_str()),
+    )
+    .await?;
+    log::info!("Program {} hidden by {}", program, operator);
+
+    Ok((StatusCode::OK, Json(hidden)))
+}
+
+async fn unhide_nest(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested program {} be shown again", program);
+    validate_managed_program(&state, &db, &program).await?;
+
+    let mut conn = db.conn().await?;
+    if !db::timed(conn.breaker(), HiddenProgram::unhide(&mut conn, &program)).await? {
+        return Err(Error::NotFound(format!(
+            "Program {} is not hidden",
+            program
+        )));
+    }
+    log::info!("Program {} shown again by {}", program, operator);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "program": program, "hidden": false })),
+    ))
+}
+
+async fn assign_machine(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+    extract::Json(params): extract::Json<MachineAssignParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!(
+        "Requested program {} be moved to machine {}",
+        program,
+        params.machine
+    );
+    state.config().check_managed(params.machine.as_str())?;
+    validate_managed_program(&state, &db, &program).await?;
+
+    let mut conn = db.conn().await?;
+    let previous = db::timed(
+        conn.breaker(),
+        Program::reassign_machine(&mut conn, &program, &params.machine),
+    )
+    .await?;
+    state.cache_program_machine(
+        (db.plant.clone(), program.clone()),
+        Some(params.machine.to_string()),
+    );
+    log::info!(
+        "Program {} reassigned from machine {} to {} by {}",
+        program,
+        previous,
+        params.machine,
+        operator
+    );
+
+    // the program is already reassigned, so a failed NC move does not fail the request
+    let (nc_moved, nc_move_pending) =
+        match nc::move_nc_program(&program, &previous, params.machine.as_str()).await {
+            Ok(moved) => (moved, false),
+            // file share hiccups are retried in the background
+            Err(e @ Error::IoError(_)) => {
+                state
+                    .pending_nc_moves
+                    .lock()
+                    .await
+                    .queue(&program, &previous, params.machine.as_str(), &e)
+                    .await;
+                (false, true)
+            }
+            Err(e) => {
+                log::error!("Failed to move NC file of program {}", program);
+                log::error!("{:#?}", e);
+                (false, false)
+            }
+        };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "program": program,
+            "previousMachine": previous,
+            "machine": params.machine,
+            "ncMoved": nc_moved,
+            "ncMovePending": nc_move_pending,
+        })),
+    ))
+}
+
+async fn update_program(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+    extract::Json(params): extract::Json<ProgramUpdateParams>,
+) -> Result<(StatusCode, Json<Value>)> {
+    params.validate()?;
+
+    let queued = transition_program(
+        &state,
+        &db,
+        &operator,
+        &program,
+        Some(&params.batch),
+        params.state,
+        None,
+        params.trans_type.as_deref(),
+    )
+    .await?;
+
+    match queued {
+        true => Ok((StatusCode::ACCEPTED, Json(Value::Null))),
+        false => Ok((StatusCode::CREATED, Json(Value::Null))),
+    }
+}
+
+async fn patch_program(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    Path(program): Path<String>,
+    extract::Json(params): extract::Json<ProgramPatchParams>,
+) -> Result<(StatusCode, Json<ProgramStatus>)> {
+    log::debug!(
+        "Requested partial update of program {}: {:?}",
+        program,
+        params
+    );
+    params.validate()?;
+    validate_managed_program(&state, &db, &program).await?;
+
+    let state = Arc::clone(&state);
+    // connections are only held for a query, as transitions take their own from the pool
+    let current = {
+        let mut conn = db.conn().await?;
+        db::timed(
+            conn.breaker(),
+            ProgramStatus::get(&mut conn, &state.config().query_settings(), &program),
+        )
+        .await?
+    };
+
+    // fields not present in the request are carried over from the current status
+    let batch = params.batch.or(current.batch);
+    let queued = match (params.state, current.current_state) {
+        (Some(to), _) => {
+            transition_program(
+                &state,
+                &db,
+                &operator,
+                &program,
+                batch.as_deref(),
+                to,
+                None,
+                None,
+            )
+            .await?
+        }
+        (None, Some(current_state)) => {
+            log::trace!(
+                "Program {} assigned batch {:?} by {}",
+                program,
+                batch,
+                operator
+            );
+            let mut conn = db.conn().await?;
+            db::timed(
+                conn.breaker(),
+                StateLogEntry::record(
+                    &mut conn,
+                    &program,
+                    batch.as_deref(),
+                    current_state,
+                    operator.as_str(),
+                    None,
+                ),
+            )
+            .await?;
+            false
+        }
+        (None, None) => {
+            return Err(Error::BadRequest(format!(
+                "Program {} has no state to assign a batch to",
+                program
+            )));
+        }
+    };
+
+    // a completion that is not posted yet is accepted, like on update
+    let mut conn = db.conn().await?;
+    let status = db::timed(
+        conn.breaker(),
+        ProgramStatus::get(&mut conn, &state.config().query_settings(), &program),
+    )
+    .await?;
+    match queued {
+        true => Ok((StatusCode::ACCEPTED, Json(status))),
+        false => Ok((StatusCode::OK, Json(status))),
+    }
+}
+
+async fn cancel_programs(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    operator: Operator,
+    extract::Json(params): extract::Json<CancelParams>,
+) -> Result<(StatusCode, Json<Vec<CancelResult>>)> {
+    log::debug!(
+        "Requested cancellation of {} programs by {}: {}",
+        params.programs.len(),
+        operator,
+        params.reason
+    );
+    params.validate()?;
+
+    // cancelled one at a time so each program keeps its current batch
+    let mut results = Vec::with_capacity(params.programs.len());
+    for program in params.programs {
+        let cancelled = async {
+            let mut conn = db.conn().await?;
+            let batch = db::timed(conn.breaker(), StateLogEntry::latest(&mut conn, &program))
+                .await?
+                .and_then(|entry| entry.batch);
+            drop(conn);
+
+            transition_program(
+                &state,
+                &db,
+                &operator,
+                &program,
+                batch.as_deref(),
+                ProgramState::Cancelled,
+                Some(params.reason.trim()),
+                None,
+            )
+            .await
+        }
+        .await;
+
+        results.push(match cancelled {
+            Ok(_) => CancelResult {
+                program,
+                cancelled: true,
+                error: None,
+            },
+            Err(e) => {
+                log::warn!("Failed to cancel program {}: {}", program, e);
+                CancelResult {
+                    program,
+                    cancelled: false,
+                    error: Some(error_detail(e)),
+                }
+            }
+        });
+    }
+
+    let cancelled = results.iter().filter(|result| result.cancelled).count();
+    log::info!(
+        "Cancelled {} of {} programs by {}",
+        cancelled,
+        results.len(),
+        operator
+    );
+
+    Ok((StatusCode::OK, Json(results)))
+}
+
+/// log a program state change and perform the side effects of the new state
+///
+/// Completions are posted to SimTrans as `trans_type`, or the first configured
+/// transaction type if not given, see [`apply_transition`].
+///
+/// Returns whether a completion was queued to be posted later, because
+/// SimTrans is paused or completions are buffered, rather than posted.
+#[allow(clippy::too_many_arguments)]
+async fn transition_program(
+    state: &Arc<AppState>,
+    db: &PlantDb,
+    operator: &Operator,
+    program: &String,
+    batch: Option<&str>,
+    to: ProgramState,
+    reason: Option<&str>,
+    trans_type: Option<&str>,
+) -> Result<bool> {
+    let trans_type = state.config().simtrans_trans_type(trans_type)?;
+
+    validate_managed_program(state, db, program).await?;
+
+    // held until the transition is done, so concurrent updates cannot both pass validation
+    let _lock = state.program_locks.lock(&db.plant, program).await;
+
+    {
+        let mut conn = db.conn().await?;
+        let current = db::timed(conn.breaker(), StateLogEntry::latest(&mut conn, program)).await?;
+        validate_transition(program, current.map(|entry| entry.state), to)?;
+    }
+
+    if to == ProgramState::Processing {
+        validate_machine_online(state, db, program).await?;
+    }
+    if to == ProgramState::Complete {
+        validate_batch(state, db, program, batch).await?;
+
+        // nothing is recorded for the completion of a program that does not exist
+        let mut conn = db.conn().await?;
+        db::timed(
+            conn.breaker(),
+            simtrans::completion_repeat(&mut conn, program),
+        )
+        .await?;
+    }
+
+    apply_transition(state, db, operator, program, batch, to, reason, &trans_type).await
+}
+
+/// perform a validated state change, see [`transition_program`]
+///
+/// A completion is posted to SimTrans before the state change is logged,
+/// published and its sheet taken from the batch. A failed post is returned as
+/// [`Error::SimTrans`] with nothing recorded, so the completion can be retried.
+#[allow(clippy::too_many_arguments)]
+async fn apply_transition(
+    state: &Arc<AppState>,
+    db: &PlantDb,
+    operator: &Operator,
+    program: &str,
+    batch: Option<&str>,
+    to: ProgramState,
+    reason: Option<&str>,
+    trans_type: &str,
+) -> Result<bool> {
+    let batch_name = batch.unwrap_or_default();
+
+    let queued = match to {
+        ProgramState::Complete => post_completion(state, db, operator, program, trans_type).await?,
+        _ => false,
+    };
+
+    {
+        let mut conn = db.conn().await?;
+        let logged = db::timed(
+            conn.breaker(),
+            StateLogEntry::record(&mut conn, program, batch, to, operator.as_str(), reason),
+        )
+        .await;
+
+        if let Err(e) = logged {
+            log::error!("Failed to log state change of program {}", program);
+            log::error!("{:#?}", e);
+        }
+    }
+
+    state.publish_state(db, operator, program, batch, to).await;
+
+    match to {
+        ProgramState::Initiated => log::trace!("Program {} initiated", program),
+        ProgramState::Processing => {
+            // TODO: move NC"
+            log::trace!(
+                "Program {} is moved to processing with batch {}",
+                program,
+                batch_name
+            );
+        }
+        ProgramState::Complete => {
+            log::info!(
+                "Program {} complete with batch {} by {}",
+                program,
+                batch_name,
+                operator
+            );
+
+            // the program is complete either way, so a failed update is only logged
+            match state.consume_batch(batch_name, SHEETS_PER_COMPLETION).await {
+                Ok(remaining) => log::trace!("Batch {} has {} sheets left", batch_name, remaining),
+                Err(e) => {
+                    log::error!("Failed to record consumption of batch {}", batch_name);
+                    log::error!("{:#?}", e);
+                }
+            }
+        }
+        ProgramState::Cancelled => log::trace!("Program {} cancelled", program),
+    }
+
+    Ok(queued)
+}
+
+/// post a program completion to SimTrans, or queue it to be posted later
+///
+/// Returns whether the completion was queued, because SimTrans is paused or
+/// completions are buffered, rather than posted.
+async fn post_completion(
+    state: &Arc<AppState>,
+    db: &PlantDb,
+    operator: &Operator,
+    program: &str,
+    trans_type: &str,
+) -> Result<bool> {
+    // held in the buffer, and posted by the flush task once SimTrans is resumed
+    if !state.simtrans_enabled.load(Ordering::Acquire) {
+        log::info!("SimTrans is paused, queueing completion of {}", program);
+        let completion = PendingSimTrans::new(program, &db.plant, operator.as_str(), trans_type);
+        state.simtrans_buffer.push(completion, None).await;
+        return Ok(true);
+    }
+
+    // stored procedures are called one completion at a time
+    let config = state.config();
+    if let (Some(size), None) = (config.simtrans_buffer_size, &config.simtrans_proc) {
+        log::trace!("Buffering SimTrans completion of {}", program);
+        let completion = PendingSimTrans::new(program, &db.plant, operator.as_str(), trans_type);
+        state.simtrans_buffer.push(completion, Some(size)).await;
+        return Ok(true);
+    }
+
+    // issue SimTrans update
+    let posted = async {
+        let mut conn = db.conn().await?;
+        let breaker = conn.breaker();
+        let posted = simtrans::post_program_complete(
+            &mut conn,
+            program,
+            config.simtrans_district,
+            operator.as_str(),
+            trans_type,
+            config.simtrans_proc.as_deref(),
+        );
+        db::timed(breaker, posted).await
+    };
+    match posted.await {
+        Ok(()) => Ok(false),
+        // nothing was posted, so the completion must not be reported as done
+        Err(e @ Error::NotFound(_)) => Err(e),
+        Err(e) => {
+            log::error!("Failed to push program update to SimTrans");
+            log::error!("{:#?}", e);
+            Err(Error::simtrans(e))
+        }
+    }
+}
+
+/// check that a program may be moved from its current state to another
+fn validate_transition(program: &str, from: Option<ProgramState>, to: ProgramState) -> Result<()> {
+    match from {
+        Some(from) if !from.can_transition_to(to) => Err(Error::Conflict(format!(
+            "Program {} cannot be moved from {} to {}",
+            program,
+            from.as_str(),
+            to.as_str()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// check that the machine a program is queued on is not offline
+async fn validate_machine_online(state: &Arc<AppState>, db: &PlantDb, program: &str) -> Result<()> {
+    let machine = match state.program_machine(db, program).await? {
+        Some(machine) => machine,
+        None => return Ok(()),
+    };
+
+    match state
+        .machine_statuses
+        .lock()
+        .await
+        .is_offline(&db.plant, &machine)
+    {
+        true => Err(Error::Conflict(format!(
+            "Program {} is queued on machine {}, which is offline",
+            program, machine
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// check that the machine a program is queued on is managed by this server
+///
+/// Programs that are not queued on a machine can be changed by any server.
+/// The machine is always read from the database rather than the cache of
+/// [`AppState::program_machine`], as programs can be moved by other servers.
+async fn validate_managed_program(
+    state: &Arc<AppState>,
+    db: &PlantDb,
+    program: &str,
+) -> Result<()> {
+    if state.config().managed_machines.is_none() {
+        return Ok(());
+    }
+
+    let mut conn = db.conn().await?;
+    match db::timed(conn.breaker(), Program::get_machine(&mut conn, program)).await? {
+        Some(machine) => state.config().check_managed(&machine),
+        None => Ok(()),
+    }
+}
+
+/// check that a batch can be used for the sheet a program is nested on
+async fn validate_batch(
+    state: &Arc<AppState>,
+    db: &PlantDb,
+    program: &String,
+    batch: Option<&str>,
+) -> Result<()> {
+    let batch = match batch {
+        Some(batch) if !batch.is_empty() => batch,
+        _ => {
+            return Err(Error::BadRequest(format!(
+                "Program {} has no batch assigned",
+                program
+            )))
+        }
+    };
+
+    let mut conn = db.conn().await?;
+    let nest = db::timed(
+        conn.breaker(),
+        Nest::get(&mut conn, &state.config().query_settings(), program),
+    )
+    .await?;
+
+    let batches = state.batches().await?;
+    match batches.iter().find(|bat| bat.id == batch) {
+        Some(bat) if bat.matches_sheet(&nest.sheet) => Ok(()),
+        Some(bat) => Err(Error::Conflict(format!(
+            "Batch {} is for sheet {}, but program {} is nested on sheet {}",
+            batch, bat.sheet_name, program, nest.sheet.sheet_name
+        ))),
+        None => Err(Error::NotFound(format!("Batch {} not found", batch))),
+    }
+}
+
+async fn get_simtrans_transactions(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Query(params): Query<TransactionRangeParams>,
+) -> Result<(StatusCode, Json<Vec<PostedTransaction>>)> {
+    log::debug!("Requested SimTrans transactions {:?}", params);
+
+    let (since, until) = params.range()?;
+    let config = state.config();
+    let trans_type = params
+        .r#type
+        .as_deref()
+        .unwrap_or(&config.simtrans_trans_types[0]);
+    if !trans_type.starts_with("SN") {
+        return Err(Error::BadRequest(format!(
+            "`{}` is not a SimTrans transaction type",
+            trans_type
+        )));
+    }
+
+    let mut conn = db.conn().await?;
+    let transactions = db::timed(
+        conn.breaker(),
+        PostedTransaction::get_range(&mut conn, trans_type, since, until),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(transactions)))
+}
+
+async fn get_throughput(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Query(params): Query<ThroughputParams>,
+) -> Result<(StatusCode, Json<Vec<MachineThroughput>>)> {
+    log::debug!("Requested throughput report {:?}", params);
+
+    let (since, until) = date_range(params.since, params.until, MAX_REPORT_RANGE_DAYS)?;
+    let machine = params.machine.as_ref().map(AsRef::as_ref);
+
+    let mut conn = db.conn().await?;
+    let throughput = db::timed(
+        conn.breaker(),
+        MachineThroughput::get_range(
+            &mut conn,
+            &state.config().query_settings(),
+            machine,
+            since,
+            until,
+        ),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(throughput)))
+}
+
+async fn get_pool_state(db: PlantDb) -> (StatusCode, Json<Value>) {
+    log::debug!("Requested database pool state");
+
+    // bb8 does not expose the number of tasks waiting on a connection
+    let pool = db.pool.state();
+    (
+        StatusCode::OK,
+        Json(json!({
+            "plant": db.plant,
+            "replica": db.replica.is_some(),
+            "maxSize": db.max_size,
+            "connections": pool.connections,
+            "idleConnections": pool.idle_connections,
+            "inUse": pool.connections - pool.idle_connections,
+        })),
+    )
+}
+
+async fn get_queries(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    log::debug!("Requested query templates");
+
+    let queries: serde_json::Map<String, Value> =
+        db::api::queries(&state.config().query_settings())
+            .into_iter()
+            .map(|(endpoint, sql)| (endpoint.into(), sql.trim().into()))
+            .collect();
+
+    (StatusCode::OK, Json(Value::Object(queries)))
+}
+
+async fn pause_simtrans(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    log::info!("SimTrans pushes paused");
+
+    state.simtrans_enabled.store(false, Ordering::Release);
+    let pending = state.simtrans_buffer.pending().await;
+
+    (
+        StatusCode::OK,
+        Json(json!({ "paused": true, "pending": pending })),
+    )
+}
+
+async fn resume_simtrans(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    log::info!("SimTrans pushes resumed");
+
+    state.simtrans_enabled.store(true, Ordering::Release);
+
+    // completions queued while paused are posted now, and any that fail to
+    // post are retried by the flush task
+    let config = state.config();
+    let flushed = state
+        .simtrans_buffer
+        .flush(
+            &state.plants,
+            config.simtrans_district,
+            config.simtrans_proc.as_deref(),
+        )
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "paused": false,
+            "flushed": flushed.posted,
+            "dropped": flushed.dropped,
+            "pending": flushed.pending,
+        })),
+    )
+}
+
+async fn get_config(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Value>) {
+    log::debug!("Requested effective config");
+
+    let config = state.config();
+    let live: serde_json::Map<String, Value> = config
+        .settings()
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect();
+    let restart: serde_json::Map<String, Value> = Config::restart_settings()
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "configFile": config_file(),
+            "live": live,
+            "restart": restart,
+            "deferred": config.deferred(),
+        })),
+    )
+}
+
+async fn get_logs(Query(params): Query<LogTailParams>) -> Result<Response> {
+    let lines = params.lines.unwrap_or(DEFAULT_LOG_TAIL_LINES);
+    log::debug!("Requested last {} lines of the server log", lines);
+
+    if !(1..=logs::MAX_TAIL_LINES).contains(&lines) {
+        return Err(Error::BadRequest(format!(
+            "Lines must be between 1 and {}",
+            logs::MAX_TAIL_LINES
+        )));
+    }
+
+    // only the server's own log file is read, never a path from the request
+    let tail = logs::tail(lines).await?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        tail,
+    )
+        .into_response())
+}
+
+async fn stream_logs(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> Response {
+    log::info!("Live log stream opened");
+
+    let lines = state.log_stream.subscribe();
+    ws.on_upgrade(|socket| send_log_lines(socket, lines))
+}
+
+/// send log lines to a websocket until it is closed
+async fn send_log_lines(mut socket: WebSocket, mut lines: broadcast::Receiver<String>) {
+    loop {
+        let line = match lines.recv().await {
+            Ok(line) => line,
+            // lines are dropped rather than holding up logging, so say how many
+            Err(RecvError::Lagged(dropped)) => format!("... {} log lines dropped", dropped),
+            Err(RecvError::Closed) => break,
+        };
+
+        // nothing is logged here, as that would be streamed back in a loop
+        if socket.send(Message::Text(line)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// send state changes of programs queued on a machine over a websocket
+///
+/// Each change is sent as the JSON of a [`ProgramEvent`]. If the client falls
+/// behind, `{"lagged": <missed>}` says how many changes it missed.
+async fn stream_machine_events(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Path(machine): Path<MachineName>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    log::debug!("Requested live events of machine {}", machine.as_str());
+
+    let events = state.events.subscribe();
+    ws.on_upgrade(move |socket| send_machine_events(socket, events, db.plant, machine))
+}
+
+/// send a machine's program events to a websocket until it is closed
+async fn send_machine_events(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<ProgramEvent>,
+    plant: String,
+    machine: MachineName,
+) {
+    loop {
+        let message = match events.recv().await {
+            Ok(event) if event.plant == plant && event.is_for_machine(&machine) => {
+                serde_json::to_string(&event).expect("program events are serializable")
+            }
+            Ok(_) => continue,
+            Err(RecvError::Lagged(missed)) => json!({ "lagged": missed }).to_string(),
+            Err(RecvError::Closed) => break,
+        };
+
+        if socket.send(Message::Text(message)).await.is_err() {
+            log::debug!("Live events of machine {} closed", machine.as_str());
+            break;
+        }
+    }
+}
+
+/// stream program state changes of the request's plant as server-sent events
+///
+/// Each change is a `program` event with the JSON of a [`ProgramEvent`], the
+/// same events `GET /ws/:machine` sends for one machine. If the client falls
+/// behind, a `lagged` event says how many changes it missed.
+async fn stream_events(
+    State(state): State<Arc<AppState>>,
+    db: PlantDb,
+    Query(params): Query<EventParams>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    log::debug!(
+        "Requested event stream of plant {} (machine {:?})",
+        db.plant,
+        params.machine
+    );
+
+    let plant = db.plant;
+    let wanted = move |event: &ProgramEvent| {
+        event.plant == plant
+            && match &params.machine {
+                Some(machine) => event.is_for_machine(machine),
+                None => true,
+            }
+    };
+
+    let events = BroadcastStream::new(state.events.subscribe()).filter_map(move |event| {
+        let event = match event {
+            Ok(event) if wanted(&event) => Event::default()
+                .event("program")
+                .json_data(&event)
+                .expect("program events are serializable"),
+            Ok(_) => return None,
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                Event::default().event("lagged").data(missed.to_string())
+            }
+        };
+
+        Some(Ok(event))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn get_pending_nc_moves(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<Vec<PendingMove>>) {
+    log::debug!("Requested pending NC moves");
+
+    let pending = state.pending_nc_moves.lock().await.pending().to_vec();
+
+    (StatusCode::OK, Json(pending))
+}
+
+async fn reload_config(State(state): State<Arc<AppState>>) -> Result<(StatusCode, Json<Value>)> {
+    log::info!("Reloading config");
+
+    let config = Config::load()?;
+
+    let mut current = state.config.write().unwrap();
+    let applied = config.changed(&current);
+    let deferred = config.deferred().to_vec();
+    config.apply();
+    *current = Arc::new(config);
+    drop(current);
+
+    log::info!(
+        "Config reloaded (applied: {:?}, deferred: {:?})",
+        applied,
+        deferred
+    );
+    if !deferred.is_empty() {
+        log::warn!("Restart the server to apply {:?}", deferred);
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "applied": applied, "deferred": deferred })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use sigmanest_interface::batch::BatchType;
+
+    use super::*;
+
+    #[test]
+    fn every_listed_route_has_a_handler() {
+        // panics on a listed route without a handler, or on conflicting routes
+        let _router = router(WriteKey::from_env(), true);
+    }
+
+    /// database of a plant that refuses connections, failing within 100ms
+    fn unreachable_db() -> PlantDb {
+        let mut config = tiberius::Config::new();
+        config.host("127.0.0.1");
+        config.port(1);
+        let pool: db::DbPool = bb8::Pool::builder()
+            .connection_timeout(std::time::Duration::from_millis(100))
+            .build_unchecked(db::ConnectionManager::new(config, false));
+
+        PlantDb {
+            plant: "test".into(),
+            pool: pool.clone(),
+            primary: pool,
+            replica: None,
+            max_size: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_simtrans_post_records_nothing() {
+        std::env::set_var("SNDB_USER", "test");
+        std::env::set_var("SNDB_PWD", "test");
+        let config = Config::load().unwrap();
+        let state = Arc::new(AppState::new(config, LogStream::new()).await.unwrap());
+        *state.batches.lock().await = Some(vec![Batch {
+            id: "B1234".into(),
+            mm: "50/50W-0500".into(),
+            sheet_name: "S1234".into(),
+            r#type: BatchType::New,
+            qty: 2,
+        }]);
+        let mut events = state.events.subscribe();
+
+        let completed = apply_transition(
+            &state,
+            &unreachable_db(),
+            &Operator("operator".into()),
+            "1200X-01",
+            Some("B1234"),
+            ProgramState::Complete,
+            None,
+            "SN70",
+        )
+        .await;
+
+        match completed {
+            Err(e @ Error::SimTrans(_)) => {
+                assert_eq!(
+                    e.into_response().status(),
+                    StatusCode::INTERNAL_SERVER_ERROR
+                )
+            }
+            other => panic!("expected the SimTrans post to fail, got {:?}", other),
+        }
+        // the state change is logged just before it is published
+        assert!(events.try_recv().is_err());
+        assert_eq!(state.batches().await.unwrap()[0].qty, 2);
+    }
 }
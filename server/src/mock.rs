@@ -0,0 +1,134 @@
+//! Canned machines, programs and nests served without a database
+//!
+//! Only compiled with the `mock` feature, for developing and demoing the UI
+//! offline. The endpoints are served under `/mock`, in the same shape as the
+//! endpoints they stand in for.
+
+use axum::{extract::Path, http::StatusCode, routing::get, Json, Router};
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+
+use crate::{
+    db::api::{iso8601_duration, MachineProgram, Nest, Part, Program, Remnant, Sheet},
+    machine::{MachineActivity, MachineStatus},
+    Error, Result,
+};
+
+#[cfg(not(debug_assertions))]
+compile_error!("the `mock` feature serves fake data and must not be enabled in release builds");
+
+/// machines of the fixtures
+const MACHINES: [&str; 2] = ["Gemini", "Titan"];
+
+/// programs of the fixtures: program, machine, repeats, cutting time in seconds
+const PROGRAMS: [(&str, &str, i32, f64); 4] = [
+    ("MOCK-1001", "Gemini", 1, 1845.0),
+    ("MOCK-1002", "Gemini", 2, 960.5),
+    ("MOCK-1003", "Titan", 1, 3120.0),
+    ("MOCK-1004", "Titan", 3, 420.25),
+];
+
+/// routes serving the fixtures, to be nested under `/mock`
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/machines", get(get_machines))
+        .route("/:machine", get(get_programs))
+        .route("/nest/:nest", get(get_nest))
+}
+
+async fn get_machines() -> (StatusCode, Json<Value>) {
+    log::debug!("Requested mock machines list");
+
+    (StatusCode::OK, Json(json!(MACHINES)))
+}
+
+async fn get_programs(Path(machine): Path<String>) -> Result<(StatusCode, Json<Value>)> {
+    log::debug!("Requested mock programs for machine {}", machine);
+
+    if !MACHINES.contains(&machine.as_str()) {
+        return Err(Error::NotFound(format!("Machine {} not found", machine)));
+    }
+
+    let programs: Vec<MachineProgram> = PROGRAMS
+        .iter()
+        .filter(|(_, on_machine, ..)| *on_machine == machine)
+        .map(|&(program, _, repeats, cutting_time)| MachineProgram {
+            program: program.into(),
+            repeats,
+            cutting_time,
+            cutting_time_iso: iso8601_duration(cutting_time),
+            due_date: NaiveDate::from_ymd_opt(2024, 7, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0)),
+            priority: 0,
+            just_completed: false,
+            hidden: false,
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "machine": machine,
+            "programs": programs,
+            "activity": MachineActivity::of_queue(&programs),
+            "truncated": false,
+            "status": MachineStatus::Online,
+            "downtime": null,
+        })),
+    ))
+}
+
+async fn get_nest(Path(program): Path<String>) -> Result<(StatusCode, Json<Nest>)> {
+    log::debug!("Requested mock program {}", program);
+
+    let (index, &(name, machine, _, cutting_time)) = PROGRAMS
+        .iter()
+        .enumerate()
+        .find(|(_, (name, ..))| *name == program)
+        .ok_or_else(|| Error::NotFound(format!("Program {} not found", program)))?;
+    let id = index as i32 + 1;
+
+    let nest = Nest {
+        archive_packet_id: 9000 + id,
+        program: Program {
+            program_name: name.into(),
+            repeat_id: 1,
+            machine_name: machine.into(),
+            cutting_time,
+        },
+        parts: vec![
+            Part {
+                part_name: format!("1200{}A-X1", id),
+                part_qty: 4,
+                job: "1200000A".into(),
+                shipment: id,
+                true_area: 1_152.0,
+                nested_area: 1_296.0,
+            },
+            Part {
+                part_name: format!("1200{}A-X2", id),
+                part_qty: 2,
+                job: "1200000A".into(),
+                shipment: id,
+                true_area: 2_304.0,
+                nested_area: 2_592.0,
+            },
+        ],
+        sheet: Sheet {
+            sheet_name: "50/50W-0008".into(),
+            material_master: "50/50W-0008".into(),
+            is_singleton: false,
+        },
+        remnants: vec![Remnant {
+            remnant_name: format!("{}-R1", name),
+            length: 48.0,
+            width: 96.0,
+            area: 4_608.0,
+        }],
+    };
+
+    Ok((StatusCode::OK, Json(nest)))
+}
@@ -0,0 +1,216 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// File extension of NC programs
+const NC_EXTENSION: &str = "nc";
+
+/// Wait before the first retry of a failed NC move, doubled after every failure
+pub const NC_RETRY_BACKOFF: Duration = Duration::seconds(30);
+
+/// Longest wait between retries of a failed NC move
+pub const NC_RETRY_MAX_BACKOFF: Duration = Duration::hours(1);
+
+/// Root directory of NC programs, from env `SN_NC_DIR`
+///
+/// NC programs for each machine are kept in a subdirectory named for the machine.
+pub fn nc_root() -> Option<PathBuf> {
+    std::env::var_os("SN_NC_DIR").map(PathBuf::from)
+}
+
+/// path of a program's NC file for a machine
+pub fn nc_path(root: &Path, machine: &str, program: &str) -> Result<PathBuf> {
+    for name in [machine, program] {
+        if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+            return Err(Error::BadRequest(format!(
+                "`{}` cannot be used as an NC path",
+                name
+            )));
+        }
+    }
+
+    Ok(root
+        .join(machine)
+        .join(format!("{}.{}", program, NC_EXTENSION)))
+}
+
+/// check if a program has an NC file for a machine
+///
+/// Returns `None` if no NC directory is configured.
+pub async fn has_nc_program(machine: &str, program: &str) -> Result<Option<bool>> {
+    let root = match nc_root() {
+        Some(root) => root,
+        None => return Ok(None),
+    };
+
+    let path = nc_path(&root, machine, program)?;
+    Ok(Some(tokio::fs::try_exists(&path).await?))
+}
+
+/// move a program's NC file from one machine's directory to another's
+///
+/// Returns `false` if no NC directory is configured or the program has no NC file.
+pub async fn move_nc_program(program: &str, from: &str, to: &str) -> Result<bool> {
+    let root = match nc_root() {
+        Some(root) => root,
+        None => return Ok(false),
+    };
+
+    let source = nc_path(&root, from, program)?;
+    if !tokio::fs::try_exists(&source).await? {
+        log::warn!("No NC file found for program {} at {:?}", program, source);
+        return Ok(false);
+    }
+
+    let target = nc_path(&root, to, program)?;
+    tokio::fs::rename(&source, &target).await?;
+    log::info!("Moved NC file {:?} to {:?}", source, target);
+
+    Ok(true)
+}
+
+/// File of NC moves waiting to be retried, from env `SN_NC_PENDING_FILE`
+///
+/// Defaults to `nc_pending.json` in the working directory, so moves queued
+/// before a restart are still retried.
+pub fn pending_file() -> PathBuf {
+    std::env::var_os("SN_NC_PENDING_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("nc_pending.json"))
+}
+
+/// NC move that failed on a file system error, waiting to be retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingMove {
+    pub program: String,
+    pub from: String,
+    pub to: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub queued_at: DateTime<Utc>,
+    pub retry_at: DateTime<Utc>,
+}
+
+impl PendingMove {
+    /// record a failed attempt, backing off before the next one
+    fn failed(&mut self, error: &Error) {
+        self.attempts += 1;
+        self.last_error = format!("{:?}", error);
+
+        let backoff = NC_RETRY_BACKOFF * 2_i32.saturating_pow(self.attempts.min(16) - 1);
+        self.retry_at = Utc::now() + backoff.min(NC_RETRY_MAX_BACKOFF);
+    }
+}
+
+/// NC moves waiting to be retried, saved to the [`pending_file`] on every change
+#[derive(Debug, Default)]
+pub struct PendingMoves(Vec<PendingMove>);
+
+impl PendingMoves {
+    /// load the moves saved by the last run, if any
+    pub async fn load() -> Self {
+        let path = pending_file();
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        match serde_json::from_slice(&contents) {
+            Ok(moves) => Self(moves),
+            Err(e) => {
+                log::warn!("Ignoring unreadable pending NC moves {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    async fn save(&self) {
+        let contents = serde_json::to_vec(&self.0).expect("pending NC moves are serializable");
+        if let Err(e) = tokio::fs::write(pending_file(), contents).await {
+            log::error!("Failed to save pending NC moves");
+            log::error!("{:#?}", e);
+        }
+    }
+
+    /// queue a move that failed to be retried later
+    ///
+    /// A program has at most one pending move, so a newer move of the same
+    /// program replaces the older one, keeping where the file was moved from.
+    pub async fn queue(&mut self, program: &str, from: &str, to: &str, error: &Error) {
+        let from = match self.0.iter().position(|pending| pending.program == program) {
+            Some(i) => self.0.remove(i).from,
+            None => from.into(),
+        };
+
+        let mut pending = PendingMove {
+            program: program.into(),
+            from,
+            to: to.into(),
+            attempts: 0,
+            last_error: String::new(),
+            queued_at: Utc::now(),
+            retry_at: Utc::now(),
+        };
+        pending.failed(error);
+        log::warn!(
+            "Queued NC move of program {} to {}, retrying at {}",
+            program,
+            to,
+            pending.retry_at
+        );
+
+        self.0.push(pending);
+        self.save().await;
+    }
+
+    /// retry the moves that are due, returning the number that succeeded
+    ///
+    /// Moves that fail again are kept with a longer backoff, unless the
+    /// failure is not from the file system, as retrying would not help.
+    pub async fn retry_due(&mut self) -> usize {
+        let now = Utc::now();
+        if !self.0.iter().any(|pending| pending.retry_at <= now) {
+            return 0;
+        }
+
+        let mut moved = 0;
+        let mut kept = Vec::with_capacity(self.0.len());
+        for mut pending in self.0.drain(..) {
+            if pending.retry_at > now {
+                kept.push(pending);
+                continue;
+            }
+
+            match move_nc_program(&pending.program, &pending.from, &pending.to).await {
+                Ok(_) => moved += 1,
+                Err(e @ Error::IoError(_)) => {
+                    pending.failed(&e);
+                    log::warn!(
+                        "Retry {} of NC move of program {} failed: {}",
+                        pending.attempts,
+                        pending.program,
+                        pending.last_error
+                    );
+                    kept.push(pending);
+                }
+                Err(e) => {
+                    log::error!("Dropped NC move of program {}", pending.program);
+                    log::error!("{:#?}", e);
+                }
+            }
+        }
+        self.0 = kept;
+        self.save().await;
+
+        moved
+    }
+
+    /// moves waiting to be retried, oldest first
+    pub fn pending(&self) -> &[PendingMove] {
+        &self.0
+    }
+}
@@ -0,0 +1,84 @@
+use axum::{
+    extract::{Request, State},
+    http::Uri,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::routes::ROUTES;
+
+/// whether `segment` is the first path segment of a fixed route in [`ROUTES`]
+///
+/// Only these are lowercased, as `/:machine` takes a machine name as its
+/// first segment and machine names are case sensitive.
+fn is_route_segment(segment: &str) -> bool {
+    ROUTES
+        .iter()
+        .filter_map(|route| route.path[1..].split('/').next())
+        .filter(|first| !first.starts_with(':'))
+        .any(|first| first == segment)
+}
+
+/// Rewriting of request paths before they are routed
+///
+/// If env `SN_LOWERCASE_PATHS` is set, the first segment of fixed routes is
+/// matched regardless of case, so `/Machines` is routed as `/machines`.
+/// Path parameters, such as machine and program names, keep their case.
+/// Trailing slashes are trimmed by [`NormalizePathLayer`](tower_http::normalize_path::NormalizePathLayer).
+#[derive(Debug, Clone, Copy)]
+pub struct PathNormalization {
+    lowercase: bool,
+}
+
+impl PathNormalization {
+    pub fn from_env() -> Self {
+        let lowercase = std::env::var("SN_LOWERCASE_PATHS").is_ok_and(|v| v == "1" || v == "true");
+        log::debug!("lowercasing route paths: {}", lowercase);
+
+        Self { lowercase }
+    }
+
+    /// normalized form of a path
+    pub fn normalize(&self, path: &str) -> String {
+        let mut path = path.to_string();
+
+        if self.lowercase && path.len() > 1 {
+            let end = path[1..].find('/').map_or(path.len(), |end| end + 1);
+            let segment = path[1..end].to_lowercase();
+            if is_route_segment(&segment) {
+                path.replace_range(1..end, &segment);
+            }
+        }
+
+        path
+    }
+}
+
+/// middleware normalizing request paths, see [`PathNormalization`]
+///
+/// It has to wrap the router rather than be a layer of it, as layers of a
+/// router only run once a route has been matched.
+pub async fn normalize_path(
+    State(normalization): State<PathNormalization>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let normalized = normalization.normalize(path);
+
+    if normalized != path {
+        let uri = match request.uri().query() {
+            Some(query) => format!("{}?{}", normalized, query),
+            None => normalized,
+        };
+        match uri.parse::<Uri>() {
+            Ok(uri) => {
+                log::trace!("Normalized path {} to {}", request.uri(), uri);
+                *request.uri_mut() = uri;
+            }
+            Err(e) => log::warn!("Failed to normalize path {}: {}", request.uri(), e),
+        }
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,87 @@
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+
+/// Largest JSON body that is pretty printed, larger bodies are left compact
+const MAX_PRETTY_BODY: usize = 16 * 1024 * 1024;
+
+/// Pretty printing of JSON responses, for reading them while debugging
+///
+/// A request asks for it with `?pretty=true`. If env `SN_PRETTY_JSON` is set,
+/// every JSON response is pretty printed. Responses are compact otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct PrettyJson {
+    always: bool,
+}
+
+impl PrettyJson {
+    pub fn from_env() -> Self {
+        let always = std::env::var("SN_PRETTY_JSON").is_ok_and(|v| v == "1" || v == "true");
+        log::debug!("pretty printing all JSON responses: {}", always);
+
+        Self { always }
+    }
+}
+
+/// check if a query string asks for pretty printing
+fn requests_pretty(query: &str) -> bool {
+    query
+        .split('&')
+        .any(|pair| matches!(pair, "pretty" | "pretty=true" | "pretty=1"))
+}
+
+/// middleware pretty printing JSON responses, see [`PrettyJson`]
+pub async fn pretty_json(
+    State(pretty): State<PrettyJson>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let wanted = pretty.always || request.uri().query().is_some_and(requests_pretty);
+    let response = next.run(request).await;
+    if !wanted {
+        return response;
+    }
+
+    // also covers `application/problem+json` errors
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("json"));
+    if !is_json {
+        return response;
+    }
+
+    // bodies too large, or of unknown size, are passed through as they are,
+    // rather than cut off while being read
+    let size = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse().ok())
+        .or_else(|| response.body().size_hint().upper());
+    if size.is_none_or(|size| size > MAX_PRETTY_BODY as u64) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, MAX_PRETTY_BODY).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::warn!("Failed to read response to pretty print: {}", e);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) => serde_json::to_vec_pretty(&value).expect("JSON values are serializable"),
+        Err(_) => bytes.to_vec(),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(body))
+}
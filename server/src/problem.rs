@@ -0,0 +1,95 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Media type of error responses
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Largest error body from outside the server that is turned into a problem detail
+const MAX_REJECTION_BODY: usize = 16 * 1024;
+
+/// Error response body, as described by RFC 7807
+///
+/// Problems have no type of their own, so `type` is always `about:blank` and
+/// `title` is the reason phrase of the status. What went wrong is in `detail`,
+/// along with any extension members, such as the invalid `fields` of a request.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub r#type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            r#type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: detail.into(),
+            extensions: Map::new(),
+        }
+    }
+
+    /// add an extension member
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.extensions.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_vec(&self).expect("problems are serializable");
+
+        (status, [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)], body).into_response()
+    }
+}
+
+/// middleware turning error responses not built from an [`crate::Error`],
+/// such as extractor rejections and unmatched routes, into problems
+///
+/// The plain text body of the response becomes the problem detail, and its
+/// headers, such as `Allow` of a 405, are kept. Error responses with any
+/// other body are left alone.
+pub async fn problem_responses(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+
+    // responses with a body of their own, such as a `/ready` that is not ready, are kept
+    let status = response.status();
+    let is_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_none_or(|content_type| content_type.starts_with("text/plain"));
+    if !(status.is_client_error() || status.is_server_error()) || !is_text {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let detail = match to_bytes(body, MAX_REJECTION_BODY).await {
+        Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+        _ => status.canonical_reason().unwrap_or("Error").into(),
+    };
+
+    let problem =
+        serde_json::to_vec(&Problem::new(status, detail)).expect("problems are serializable");
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+    );
+
+    Response::from_parts(parts, Body::from(problem))
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Default time a batch stays reserved if the holder does not release it
+pub const DEFAULT_RESERVATION_TTL: Duration = Duration::minutes(15);
+
+/// Longest time a batch can be reserved for at once
+pub const MAX_RESERVATION_TTL: Duration = Duration::hours(24);
+
+/// time a reservation requested for `ttl_seconds` lasts, `default` if not given
+///
+/// Fails unless the time is positive and at most [`MAX_RESERVATION_TTL`].
+pub fn reservation_ttl(ttl_seconds: Option<i64>, default: Duration) -> Result<Duration> {
+    match ttl_seconds {
+        None => Ok(default),
+        Some(secs) if secs > 0 && secs <= MAX_RESERVATION_TTL.num_seconds() => {
+            Ok(Duration::seconds(secs))
+        }
+        Some(secs) => Err(Error::BadRequest(format!(
+            "ttlSeconds must be between 1 and {}, got {}",
+            MAX_RESERVATION_TTL.num_seconds(),
+            secs
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reservation {
+    pub batch: String,
+    pub holder: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Reservation {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+/// In-memory store of batch reservations, keyed by batch id
+#[derive(Debug, Default)]
+pub struct Reservations(HashMap<String, Reservation>);
+
+impl Reservations {
+    /// reserve a batch for a holder
+    ///
+    /// A holder may renew their own reservation. Fails if the batch is held
+    /// by someone else and that reservation has not expired.
+    pub fn reserve(&mut self, batch: &str, holder: &str, ttl: Duration) -> Result<Reservation> {
+        if let Some(current) = self.0.get(batch) {
+            if current.holder != holder && !current.is_expired() {
+                return Err(Error::Conflict(format!(
+                    "Batch {} is reserved by {} until {}",
+                    batch, current.holder, current.expires_at
+                )));
+            }
+        }
+
+        let reservation = Reservation {
+            batch: batch.into(),
+            holder: holder.into(),
+            expires_at: Utc::now() + ttl,
+        };
+        self.0.insert(batch.into(), reservation.clone());
+
+        Ok(reservation)
+    }
+
+    /// reserve several batches for a holder, all or nothing
+    ///
+    /// If any batch is held by someone else, none are reserved and the
+    /// conflicting reservations are returned in [`Error::ReservationConflicts`].
+    pub fn reserve_all(
+        &mut self,
+        batches: &[String],
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<Vec<Reservation>> {
+        let conflicts: Vec<Reservation> = batches
+            .iter()
+            .filter_map(|batch| self.0.get(batch))
+            .filter(|current| current.holder != holder && !current.is_expired())
+            .cloned()
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(Error::ReservationConflicts(conflicts));
+        }
+
+        batches
+            .iter()
+            .map(|batch| self.reserve(batch, holder, ttl))
+            .collect()
+    }
+
+    /// clear the reservation of a batch, regardless of holder
+    ///
+    /// Returns the cleared reservation, or `None` if the batch was not reserved.
+    pub fn release(&mut self, batch: &str) -> Option<Reservation> {
+        self.0
+            .remove(batch)
+            .filter(|reservation| !reservation.is_expired())
+    }
+
+    /// get all reservations that have not expired
+    pub fn active(&mut self) -> Vec<Reservation> {
+        self.0.retain(|_, reservation| !reservation.is_expired());
+
+        let mut reservations: Vec<Reservation> = self.0.values().cloned().collect();
+        reservations.sort_by(|a, b| a.batch.cmp(&b.batch));
+
+        reservations
+    }
+}
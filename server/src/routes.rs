@@ -0,0 +1,292 @@
+use serde::Serialize;
+
+/// Key a request needs to be served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthLevel {
+    /// served to anyone
+    None,
+    /// needs the write API key in the `X-Api-Key` header
+    Write,
+}
+
+/// Route served by the server, as listed by `GET /routes`
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteInfo {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub auth: AuthLevel,
+    /// needs the operator performing the change in the `X-Operator` header
+    pub operator: bool,
+    pub description: &'static str,
+}
+
+const fn route(method: &'static str, path: &'static str, description: &'static str) -> RouteInfo {
+    RouteInfo {
+        method,
+        path,
+        auth: AuthLevel::None,
+        operator: false,
+        description,
+    }
+}
+
+impl RouteInfo {
+    const fn write(self) -> Self {
+        Self {
+            auth: AuthLevel::Write,
+            ..self
+        }
+    }
+
+    const fn operator(self) -> Self {
+        Self {
+            operator: true,
+            ..self
+        }
+    }
+}
+
+/// Every route of the server, with the key it needs
+///
+/// The router is built from this list, so a route is only served once it is
+/// added here, and the write key is required where it is listed.
+pub const ROUTES: &[RouteInfo] = &[
+    route("GET", "/", "placeholder, not implemented yet"),
+    route("GET", "/health", "server is up, even while overloaded"),
+    route("HEAD", "/health", "server is up, without a body"),
+    route("GET", "/ready", "batches have been loaded"),
+    route("GET", "/routes", "this list of routes"),
+    route("GET", "/machines", "machines of the plant"),
+    route(
+        "HEAD",
+        "/machines",
+        "machines can be listed, without a body",
+    ),
+    route(
+        "GET",
+        "/machines/:machine/next",
+        "next program that can be run on a machine, if any",
+    ),
+    route(
+        "POST",
+        "/machines/:machine/status",
+        "take a machine offline or bring it back online",
+    )
+    .operator(),
+    route("GET", "/batches", "all batches"),
+    route(
+        "HEAD",
+        "/batches",
+        "batches are loaded, without building the list",
+    ),
+    route("GET", "/batches.csv", "all batches as CSV"),
+    route("GET", "/batches/reservations", "batches currently reserved"),
+    route("GET", "/batches/status", "how fresh the cached batches are"),
+    route(
+        "POST",
+        "/batches/reserve-bulk",
+        "reserve several batches, all or nothing",
+    )
+    .operator(),
+    route(
+        "GET",
+        "/materials",
+        "materials of the batches, with the sheets available",
+    ),
+    route(
+        "GET",
+        "/materials/:material/demand",
+        "sheets of a material needed by queued programs, against those in batches",
+    ),
+    route(
+        "GET",
+        "/batches/:program",
+        "batches a program can be cut from",
+    ),
+    route("POST", "/batches/:batch/reservation", "reserve a batch").operator(),
+    route(
+        "DELETE",
+        "/batches/:batch/reservation",
+        "release a reservation of a batch",
+    )
+    .write()
+    .operator(),
+    route(
+        "GET",
+        "/simtrans/transactions",
+        "SimTrans transactions posted in a date range",
+    ),
+    route(
+        "GET",
+        "/reports/throughput",
+        "cutting hours completed per machine per day",
+    ),
+    route(
+        "GET",
+        "/programs/unmatched",
+        "queued programs without a matching batch",
+    ),
+    route(
+        "GET",
+        "/programs/by-state/:state",
+        "programs currently in a state",
+    ),
+    route("POST", "/programs/cancel", "cancel programs").operator(),
+    route("GET", "/:machine", "programs queued on a machine"),
+    route(
+        "GET",
+        "/:machine/by-sheet",
+        "programs queued on a machine, grouped by sheet",
+    ),
+    route(
+        "GET",
+        "/nest/:nest",
+        "nest of a program, optionally only some fields",
+    ),
+    route("POST", "/nest/:nest", "move a program to another state").operator(),
+    route(
+        "PATCH",
+        "/nest/:nest",
+        "update the state or batch of a program",
+    )
+    .operator(),
+    route("POST", "/nests", "nests of several programs"),
+    route(
+        "GET",
+        "/snapshot",
+        "machines, their programs and matching batches",
+    ),
+    route(
+        "GET",
+        "/snapshot/diff",
+        "programs added, removed or changed between two times",
+    ),
+    route("GET", "/nest/:nest/status", "current state of a program"),
+    route(
+        "GET",
+        "/nest/:nest/timing",
+        "planned and actual cutting time of a program",
+    ),
+    route(
+        "GET",
+        "/nest/:nest/siblings",
+        "programs nested on the same sheet",
+    ),
+    route(
+        "GET",
+        "/nest/:nest/position",
+        "place of a program in its machine's queue",
+    ),
+    route(
+        "GET",
+        "/nest/:nest/estimate",
+        "estimated start of a queued program",
+    ),
+    route("GET", "/nest/:nest/bbox", "dimensions of a program's sheet"),
+    route(
+        "GET",
+        "/nest/:nest/remnant",
+        "expected offcut of a program's sheet",
+    ),
+    route(
+        "GET",
+        "/nest/:nest/utilization",
+        "share of a program's sheet used by parts",
+    ),
+    route(
+        "GET",
+        "/nest/:nest/related-by-part",
+        "programs sharing parts with a program",
+    ),
+    route("POST", "/nest/:nest/reprint", "paperwork of a program").operator(),
+    route("GET", "/nest/:nest/validate", "check a program can be cut"),
+    route(
+        "POST",
+        "/nest/:nest/machine",
+        "move a program to another machine",
+    )
+    .operator(),
+    route(
+        "GET",
+        "/nest/:nest/batch/:batch/check",
+        "check if a batch can be used to cut a program",
+    ),
+    route(
+        "POST",
+        "/nest/:nest/hide",
+        "hide a program from its machine's queue",
+    )
+    .operator(),
+    route(
+        "POST",
+        "/nest/:nest/unhide",
+        "show a hidden program in its machine's queue again",
+    )
+    .operator(),
+    route("GET", "/nest/:nest/priority", "priority of a program"),
+    route(
+        "POST",
+        "/nest/:nest/priority",
+        "set the priority of a program in its machine's queue",
+    )
+    .operator(),
+    route(
+        "GET",
+        "/workorders/:wo/programs",
+        "programs with parts of a work order, and their completion",
+    ),
+    route(
+        "GET",
+        "/events",
+        "server-sent events of program state changes",
+    ),
+    route(
+        "GET",
+        "/ws/:machine",
+        "websocket of state changes of a machine's programs",
+    ),
+    route("GET", "/feedback", "feedback of completed programs"),
+    route("GET", "/feedback/by-part", "feedback counts per part"),
+    route("POST", "/feedback/:id/resolve", "mark feedback resolved").operator(),
+    route("GET", "/admin/pool", "database pool state").write(),
+    route("POST", "/admin/simtrans/pause", "hold back SimTrans pushes").write(),
+    route(
+        "POST",
+        "/admin/simtrans/resume",
+        "post held back SimTrans pushes",
+    )
+    .write(),
+    route("POST", "/admin/reload-config", "reload the config").write(),
+    route("GET", "/admin/config", "effective config").write(),
+    route("GET", "/admin/nc/pending", "NC moves waiting to be retried").write(),
+    route("GET", "/admin/logs", "last lines of the server log").write(),
+    route(
+        "GET",
+        "/admin/logs/ws",
+        "websocket streaming log lines as they are written",
+    )
+    .write(),
+    route(
+        "GET",
+        "/admin/queries",
+        "query templates, only served if SN_DEBUG is set",
+    )
+    .write(),
+    route(
+        "GET",
+        "/mock/machines",
+        "fixture machines, only served with the mock feature",
+    ),
+    route(
+        "GET",
+        "/mock/:machine",
+        "fixture programs of a machine, only served with the mock feature",
+    ),
+    route(
+        "GET",
+        "/mock/nest/:nest",
+        "fixture nest of a program, only served with the mock feature",
+    ),
+];
@@ -0,0 +1,66 @@
+use serde_json::{Map, Value};
+
+use crate::Result;
+
+/// Media type of XML responses
+pub const XML_CONTENT_TYPE: &str = "application/xml";
+
+/// check if an `Accept` header prefers XML over JSON
+///
+/// JSON stays the default, so XML is only used if it is listed before any JSON type.
+pub fn accepts_xml(accept: &str) -> bool {
+    for media_type in accept.split(',') {
+        let media_type = media_type.split(';').next().unwrap_or_default().trim();
+        match media_type {
+            "application/xml" | "text/xml" => return true,
+            "application/json" | "*/*" => return false,
+            _ => (),
+        }
+    }
+
+    false
+}
+
+/// serialize a value as an XML document with a root element
+///
+/// Fields become child elements of the same (camelCase) name. Lists become
+/// an element containing one child per item, named for the singular of the
+/// list (`parts` holds `part` elements). `null` fields are empty elements.
+pub fn to_xml(root: &str, value: Value) -> Result<String> {
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    quick_xml::se::to_writer_with_root(&mut xml, root, &wrap_lists(root, value)).map_err(|e| {
+        log::error!("Failed to serialize {} as XML: {}", root, e);
+        e
+    })?;
+
+    Ok(xml)
+}
+
+/// wrap the items of lists in an element named for the singular of the list
+///
+/// Without it, items are serialized as repeated elements named for the list.
+fn wrap_lists(name: &str, value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(field, value)| {
+                    let value = wrap_lists(&field, value);
+                    (field, value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => {
+            let item_name = name.strip_suffix('s').unwrap_or("item");
+            let items = items
+                .into_iter()
+                .map(|item| wrap_lists(item_name, item))
+                .collect();
+            Value::Object(Map::from_iter([(
+                item_name.to_string(),
+                Value::Array(items),
+            )]))
+        }
+        value => value,
+    }
+}